@@ -44,6 +44,7 @@ type StatsResponse struct {
 var (
 	meiliClient *meilisearch.Client
 	limiter     *rate.Limiter
+	indexName   string
 )
 
 func main() {
@@ -62,6 +63,12 @@ func main() {
 		log.Fatal("MEILISEARCH_KEY or MEILI_MASTER_KEY must be set")
 	}
 
+	// Must match the crawler's INDEX_NAME
+	indexName = os.Getenv("INDEX_NAME")
+	if indexName == "" {
+		indexName = "web_pages"
+	}
+
 	port := os.Getenv("PORT")
 	if port == "" {
 		port = "8080"
@@ -162,7 +169,7 @@ func handleHealth(c *gin.Context) {
 }
 
 func handleStats(c *gin.Context) {
-	index := meiliClient.Index("web_pages")
+	index := meiliClient.Index(indexName)
 
 	stats, err := index.GetStats()
 	if err != nil {
@@ -222,7 +229,7 @@ func performSearch(c *gin.Context, req SearchRequest) {
 	// Search Meilisearch
 	startTime := time.Now()
 
-	index := meiliClient.Index("web_pages")
+	index := meiliClient.Index(indexName)
 	searchRes, err := index.Search(req.Query, &meilisearch.SearchRequest{
 		Limit:  int64(req.Limit),
 		Offset: int64(req.Offset),
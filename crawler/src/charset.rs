@@ -0,0 +1,158 @@
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+use url::Url;
+
+// How far into the body a <meta> charset declaration is looked for, as in browsers
+const META_PRESCAN_BYTES: usize = 1024;
+
+// Decodes an HTML body to UTF-8. The encoding comes from, in order: a byte order mark,
+// the Content-Type charset, a <meta> declaration near the start, and finally a guess
+// from the bytes themselves, hinted by the URL's top-level domain
+pub fn decode(body: &[u8], content_type: &str, url: &Url) -> (String, &'static Encoding) {
+    let encoding = Encoding::for_bom(body)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| header_charset(content_type))
+        .or_else(|| meta_charset(body))
+        .unwrap_or_else(|| guess(body, url));
+
+    // decode() strips the BOM and lets it override the encoding, like browsers do
+    let (text, encoding, _) = encoding.decode(body);
+    (text.into_owned(), encoding)
+}
+
+// `text/html; charset=windows-1252`
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches(|c| c == '"' || c == '\'').as_bytes()))
+}
+
+// `<meta charset="shift_jis">` or `<meta http-equiv="Content-Type" content="text/html; charset=gbk">`
+fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    let head = body[..body.len().min(META_PRESCAN_BYTES)].to_ascii_lowercase();
+
+    let mut rest = head.as_slice();
+    while let Some(start) = find(rest, b"<meta") {
+        let tag = &rest[start..];
+        let tag = &tag[..find(tag, b">").unwrap_or(tag.len())];
+
+        if let Some(position) = find(tag, b"charset") {
+            let value = tag[position + b"charset".len()..]
+                .iter()
+                .skip_while(|byte| byte.is_ascii_whitespace())
+                .skip_while(|byte| **byte == b'=')
+                .skip_while(|byte| byte.is_ascii_whitespace() || matches!(byte, b'"' | b'\''))
+                .take_while(|byte| !byte.is_ascii_whitespace() && !matches!(byte, b'"' | b'\'' | b';' | b'/' | b'>'))
+                .copied()
+                .collect::<Vec<_>>();
+
+            if let Some(encoding) = Encoding::for_label(&value) {
+                // A page that could be read as ASCII up to here cannot really be UTF-16
+                return Some(match encoding {
+                    encoding if encoding == UTF_16LE || encoding == UTF_16BE => UTF_8,
+                    encoding if encoding == X_USER_DEFINED => WINDOWS_1252,
+                    encoding => encoding,
+                });
+            }
+        }
+
+        rest = &rest[start + b"<meta".len()..];
+    }
+
+    None
+}
+
+fn guess(body: &[u8], url: &Url) -> &'static Encoding {
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(body, true);
+
+    // Only a domain name has a top-level domain to go by, not an IP address
+    let tld = url
+        .domain()
+        .and_then(|domain| domain.trim_end_matches('.').rsplit('.').next())
+        .filter(|tld| !tld.is_empty());
+    detector.guess(tld.map(str::as_bytes), Utf8Detection::Allow)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/fixtures/charset/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    fn decode_fixture(name: &str, content_type: &str, url: &str) -> (String, &'static str) {
+        let (text, encoding) = decode(&fixture(name), content_type, &Url::parse(url).unwrap());
+        (text, encoding.name())
+    }
+
+    #[test]
+    fn meta_charset_windows_1252() {
+        let (text, encoding) = decode_fixture("windows-1252-meta.html", "text/html", "http://example.com/");
+        assert_eq!(encoding, "windows-1252");
+        assert!(text.contains("Café “crème” – naïve"), "{}", text);
+    }
+
+    #[test]
+    fn meta_http_equiv_shift_jis() {
+        let (text, encoding) = decode_fixture("shift_jis-http-equiv.html", "text/html", "http://example.jp/");
+        assert_eq!(encoding, "Shift_JIS");
+        assert!(text.contains("日本語のページです"), "{}", text);
+    }
+
+    #[test]
+    fn header_charset_wins_over_meta() {
+        // The fixture declares windows-1252 but is served as KOI8-R
+        let (text, encoding) = decode_fixture(
+            "koi8-r-header.html",
+            "text/html; charset=\"KOI8-R\"",
+            "http://example.com/",
+        );
+        assert_eq!(encoding, "KOI8-R");
+        assert!(text.contains("Привет, мир"), "{}", text);
+    }
+
+    #[test]
+    fn bom_wins_over_header() {
+        let (text, encoding) = decode_fixture("utf-16le-bom.html", "text/html; charset=iso-8859-1", "http://example.com/");
+        assert_eq!(encoding, "UTF-16LE");
+        assert!(text.starts_with("<!DOCTYPE html>"), "BOM left in the text: {:?}", &text[..10]);
+        assert!(text.contains("বাংলা ভাষা"), "{}", text);
+
+        let (text, encoding) = decode_fixture("utf-8-bom.html", "text/html; charset=windows-1252", "http://example.com/");
+        assert_eq!(encoding, "UTF-8");
+        assert!(text.contains("বাংলা ভাষা"), "{}", text);
+    }
+
+    #[test]
+    fn undeclared_encodings_are_guessed() {
+        let (text, encoding) = decode_fixture("utf-8-undeclared.html", "text/html", "http://example.com.bd/");
+        assert_eq!(encoding, "UTF-8");
+        assert!(text.contains("বাংলাদেশের সংবাদ"), "{}", text);
+
+        let (text, encoding) = decode_fixture("gbk-undeclared.html", "text/html", "http://example.cn/");
+        assert_eq!(encoding, "GBK");
+        assert!(text.contains("中文网页"), "{}", text);
+
+        let (text, encoding) = decode_fixture("windows-1251-undeclared.html", "text/html", "http://127.0.0.1/");
+        assert_eq!(encoding, "windows-1251");
+        assert!(text.contains("Новости"), "{}", text);
+    }
+
+    #[test]
+    fn unknown_labels_fall_through() {
+        let body = b"<html><head><meta charset=\"no-such-charset\"></head><body>caf\xe9 cr\xe8me</body></html>";
+        let (text, encoding) = decode(body, "text/html; charset=bogus", &Url::parse("http://example.fr/").unwrap());
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(text.contains("café crème"), "{}", text);
+    }
+}
@@ -1,230 +1,1964 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Row};
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::{info, warn, error, debug};
 use futures::stream::{self, StreamExt};
+use reqwest::StatusCode;
+use url::Url;
 
-use crate::fetcher::Fetcher;
+use crate::error::{CrawlError, ErrorKind};
+use crate::feed;
+use crate::fetcher::{BodyLimits, FetchError, FetchOutcome, Fetcher, Validators};
+use crate::normalize::UrlNormalizer;
 use crate::parser::Parser;
-use crate::indexer_client::IndexerClient;
+use crate::recrawl::RecrawlPolicy;
+use crate::retry::RetryPolicy;
+use crate::routing::IndexRouter;
+use crate::scope::CrawlScope;
+use crate::search_backend::{generate_id, Document, SearchBackend};
+use crate::simhash::{self, NearDuplicatePolicy};
+use crate::sitemap::{self, Sitemap, SitemapSource};
+use crate::stats::CrawlStats;
+
+const BATCH_SIZE: i32 = 100;
+
+// How often expired leases are returned to the frontier
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Times per lease period that the leases of a running batch are extended
+const LEASE_RENEWALS: u64 = 3;
+
+// How often completed URLs that are due are put back into the frontier
+const RECRAWL_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Most URLs requeued per check, so one pass never holds a huge update
+const RECRAWL_BATCH_SIZE: i64 = 10_000;
+
+// How often discovered sitemaps are recorded and due ones fetched
+const SITEMAP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Most sitemaps fetched per check
+const SITEMAP_BATCH_SIZE: i64 = 10;
+
+// How often due feeds are polled
+const FEED_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// Most feeds polled per check
+const FEED_BATCH_SIZE: i64 = 20;
+
+// Frontier priority of new feed items, ahead of links (at most 10) and sitemap entries
+const FEED_ITEM_PRIORITY: i32 = 20;
+
+// How often counters are added to crawl_stats
+const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+// How long shutdown waits for the search backend to finish the last writes
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct CrawlerConfig {
+    pub concurrency: usize,
+    pub delay_ms: u64,
+    pub max_per_host: usize,
+    pub max_depth: i32,
+    pub body_limits: BodyLimits,
+    pub worker_id: String,
+    pub lease_secs: u64,
+    pub retry: RetryPolicy,
+    pub recrawl: RecrawlPolicy,
+    pub near_duplicates: NearDuplicatePolicy,
+    pub normalizer: UrlNormalizer,
+    pub scope: CrawlScope,
+    // Enqueued at depth 0 on startup
+    pub seeds: Vec<String>,
+    pub router: IndexRouter,
+    // How often buffered documents are flushed and their outcomes collected
+    pub index_flush_interval: Duration,
+    // How often each known sitemap is fetched again
+    pub sitemap_interval: Duration,
+    // Bounds for how often a feed is polled, it starts at the minimum
+    pub feed_poll: RecrawlPolicy,
+}
+
+struct FrontierUrl {
+    id: i32,
+    url: String,
+    depth: i32,
+    error_count: i32,
+    // Index holding the page's document from its last successful crawl
+    index_name: Option<String>,
+    content_hash: Option<String>,
+    recrawl_interval_secs: Option<i32>,
+    validators: Validators,
+    // Id of the document the page was last written as, see own_document_index
+    document_id: Option<String>,
+    // The earlier page this one was last found to nearly duplicate
+    duplicate_of: Option<i32>,
+}
+
+// A URL on its way into the frontier
+struct NewUrl {
+    url: String,
+    depth: i32,
+    priority: i32,
+    // The first recrawl interval, from a sitemap's changefreq
+    recrawl_interval_secs: Option<i32>,
+    // From a sitemap; a completed page modified since its last crawl is due again
+    lastmod: Option<DateTime<Utc>>,
+}
+
+// An earlier page with nearly the same content, and the document standing for it
+struct NearDuplicate {
+    id: i32,
+    index: Option<String>,
+    document_id: String,
+}
+
+struct DueSitemap {
+    id: i32,
+    url: String,
+    // Origin whose robots.txt declared the sitemap, or led to it through indexes
+    site: String,
+}
+
+struct DueFeed {
+    id: i32,
+    url: String,
+    poll_interval_secs: i32,
+}
+
+// What a successful crawl did with the page
+struct IndexedPage {
+    index: String,
+    document_id: String,
+    content_hash: String,
+    validators: Validators,
+    simhash: Option<u64>,
+    duplicate_of: Option<i32>,
+}
+
+enum PageOutcome {
+    Indexed(IndexedPage),
+    // Nothing written, a near-duplicate's document stands for the page
+    Collapsed(IndexedPage),
+    NoIndex,
+    // Not indexed, a near-duplicate of the given URL is
+    Duplicate { of: i32, simhash: u64, content_hash: String },
+    // 304 Not Modified or the same content as the indexed document; carries the
+    // validators of a full response
+    Unchanged(Option<Validators>),
+}
 
 pub struct Crawler {
     pool: PgPool,
     fetcher: Arc<Fetcher>,
     parser: Arc<Parser>,
-    indexer: Arc<IndexerClient>,
-    concurrency: usize,
-    delay_ms: u64,
-    max_depth: i32,
+    backend: Arc<dyn SearchBackend>,
+    stats: Arc<CrawlStats>,
+    config: CrawlerConfig,
 }
 
 impl Crawler {
     pub fn new(
         pool: PgPool,
-        meilisearch_url: String,
-        meilisearch_key: String,
-        concurrency: usize,
-        delay_ms: u64,
-        max_depth: i32,
+        backend: Arc<dyn SearchBackend>,
+        config: CrawlerConfig,
     ) -> Self {
-        let fetcher = Arc::new(Fetcher::new());
+        let fetcher = Arc::new(Fetcher::new(config.delay_ms, config.max_per_host, config.body_limits));
         let parser = Arc::new(Parser::new());
-        let indexer = Arc::new(IndexerClient::new(meilisearch_url, meilisearch_key));
 
         Self {
             pool,
             fetcher,
             parser,
-            indexer,
-            concurrency,
-            delay_ms,
-            max_depth,
+            backend,
+            stats: Arc::new(CrawlStats::default()),
+            config,
         }
     }
 
     pub async fn run(&self) -> Result<()> {
-        // Create index if not exists
-        self.indexer.create_index_if_not_exists().await?;
+        // Create every routed index if not exists and apply its schema
+        for index in self.config.router.indexes() {
+            self.backend.ensure_schema(index).await?;
+        }
+
+        info!("Crawler {} started with concurrency={}, delay={}ms, max_per_host={}, max_depth={}, lease={}s", 
+              self.config.worker_id, self.config.concurrency, self.config.delay_ms,
+              self.config.max_per_host, self.config.max_depth, self.config.lease_secs);
 
-        info!("Crawler started with concurrency={}, delay={}ms, max_depth={}", 
-              self.concurrency, self.delay_ms, self.max_depth);
+        if !self.config.seeds.is_empty() {
+            self.add_new_urls(&self.config.seeds, 0, None).await?;
+        }
+
+        // Take back anything a previous run left half-done
+        let recovered = self.recover_stale_leases().await?;
+        if recovered > 0 {
+            info!("Returned {} URLs from stale leases to the queue", recovered);
+        }
+
+        // Periodically return expired leases from crashed workers to the frontier
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(REAP_INTERVAL).await;
+                match Self::reap_expired_leases(&pool).await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Reaped {} expired leases", count),
+                    Err(e) => warn!("Failed to reap expired leases: {}", e),
+                }
+            }
+        });
+
+        // Periodically put completed URLs that are due for a recrawl back into the frontier
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            loop {
+                match Self::requeue_due_urls(&pool).await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Requeued {} URLs for recrawl", count),
+                    Err(e) => warn!("Failed to requeue URLs for recrawl: {}", e),
+                }
+                sleep(RECRAWL_CHECK_INTERVAL).await;
+            }
+        });
+
+        // Periodically add the counters to today's crawl_stats row
+        let pool = self.pool.clone();
+        let stats = Arc::clone(&self.stats);
+        tokio::spawn(async move {
+            loop {
+                sleep(STATS_FLUSH_INTERVAL).await;
+                if let Err(e) = stats.flush(&pool).await {
+                    warn!("Failed to write crawl stats: {}", e);
+                }
+            }
+        });
+
+        let result = tokio::select! {
+            result = self.crawl_loop() => result,
+            result = self.index_task_loop() => result,
+            result = self.sitemap_loop() => result,
+            result = self.feed_loop() => result,
+            _ = shutdown_signal() => {
+                info!("Shutdown requested, flushing index batch...");
+                Ok(())
+            }
+        };
+
+        // Don't lose documents still waiting for the next batch
+        self.drain_index_tasks().await?;
+        self.stats.flush(&self.pool).await?;
+
+        result
+    }
 
+    // Sends partially filled index batches on a timer and settles finished ones
+    async fn index_task_loop(&self) -> Result<()> {
         loop {
-            // Fetch pending URLs from database
-            let urls = self.fetch_pending_urls(100).await?;
+            sleep(self.config.index_flush_interval).await;
+            if let Err(e) = self.sync_index_tasks().await {
+                warn!("Failed to sync index tasks: {}", e);
+            }
+        }
+    }
+
+    pub async fn drain_index_tasks(&self) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_DRAIN_TIMEOUT;
+
+        self.sync_index_tasks().await?;
+        while self.backend.has_pending() {
+            if tokio::time::Instant::now() >= deadline {
+                warn!("Gave up waiting for pending index writes, their URLs will be recrawled");
+                break;
+            }
+            sleep(Duration::from_millis(500)).await;
+            self.sync_index_tasks().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn sync_index_tasks(&self) -> Result<()> {
+        self.backend.flush().await?;
+
+        for outcome in self.backend.poll_outcomes().await? {
+            match outcome.result {
+                Ok(()) => {
+                    self.stats.record_indexed(outcome.url_ids.len());
+                    self.mark_urls_completed(&outcome.url_ids).await?
+                }
+                Err(e) => {
+                    warn!("Indexing failed for {} URLs: {}", outcome.url_ids.len(), e);
+                    self.mark_urls_index_failed(&outcome.url_ids, &e).await?;
+                }
+            }
+        }
+
+        // Whatever is still waiting stays ours however long the backend takes
+        self.renew_leases(None).await?;
 
-            if urls.is_empty() {
+        Ok(())
+    }
+
+    async fn crawl_loop(&self) -> Result<()> {
+        loop {
+            if self.crawl_batch().await? == 0 {
                 info!("No pending URLs, waiting...");
                 sleep(Duration::from_secs(30)).await;
                 continue;
             }
 
-            info!("Processing {} URLs", urls.len());
-
-            // Process URLs concurrently
-            stream::iter(urls)
-                .map(|(id, url, depth)| {
-                    let fetcher = Arc::clone(&self.fetcher);
-                    let parser = Arc::clone(&self.parser);
-                    let indexer = Arc::clone(&self.indexer);
-                    let pool = self.pool.clone();
-                    let delay_ms = self.delay_ms;
-                    let max_depth = self.max_depth;
-
-                    async move {
-                        // Add delay for politeness
-                        sleep(Duration::from_millis(delay_ms)).await;
-
-                        match Self::process_url(
-                            id,
-                            &url,
-                            depth,
-                            &fetcher,
-                            &parser,
-                            &indexer,
-                            &pool,
-                            max_depth,
-                        ).await {
-                            Ok(_) => debug!("Successfully processed: {}", url),
-                            Err(e) => warn!("Failed to process {}: {}", url, e),
-                        }
+            info!("Batch complete, fetching next batch...");
+        }
+    }
+
+    // Claims one batch from the frontier and processes it, returning how many URLs were claimed
+    pub async fn crawl_batch(&self) -> Result<usize> {
+        // Fetch pending URLs from database
+        let urls = self.fetch_pending_urls(BATCH_SIZE).await?;
+        let count = urls.len();
+
+        if count == 0 {
+            return Ok(0);
+        }
+
+        info!("Processing {} URLs", count);
+
+        // Process URLs concurrently, politeness is enforced per host by the fetcher
+        let ids = urls.iter().map(|entry| entry.id).collect::<Vec<_>>();
+        let batch = stream::iter(Self::interleave_hosts(urls))
+            .map(|entry| async move {
+                match self.process_url(&entry).await {
+                    Ok(_) => debug!("Successfully processed: {}", entry.url),
+                    Err(e) => warn!("Failed to process {}: {}", entry.url, e),
+                }
+            })
+            .buffer_unordered(self.config.concurrency)
+            .collect::<Vec<_>>();
+        tokio::pin!(batch);
+
+        // Politeness delays on a busy host can stretch a batch past one lease, so its rows
+        // are renewed while it runs
+        let renew_every = Duration::from_secs(self.config.lease_secs / LEASE_RENEWALS).max(Duration::from_secs(1));
+        let mut renewal = tokio::time::interval_at(tokio::time::Instant::now() + renew_every, renew_every);
+        loop {
+            tokio::select! {
+                _ = &mut batch => break,
+                _ = renewal.tick() => {
+                    if let Err(e) = self.renew_leases(Some(&ids)).await {
+                        warn!("Failed to renew leases: {}", e);
                     }
-                })
-                .buffer_unordered(self.concurrency)
-                .collect::<Vec<_>>()
-                .await;
+                }
+            }
+        }
 
-            info!("Batch complete, fetching next batch...");
+        Ok(count)
+    }
+
+    // Extends our leases on the given rows, or on every row still waiting for the search
+    // backend when `ids` is None
+    async fn renew_leases(&self, ids: Option<&[i32]>) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE urls 
+             SET leased_until = NOW() + make_interval(secs => $3) 
+             WHERE worker_id = $2 
+               AND (status = 'indexing' OR (status = 'processing' AND id = ANY($1)))"
+        )
+        .bind(ids.unwrap_or_default())
+        .bind(&self.config.worker_id)
+        .bind(self.config.lease_secs as f64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Round-robin the batch across hosts so one busy host doesn't hold every worker slot
+    fn interleave_hosts(urls: Vec<FrontierUrl>) -> Vec<FrontierUrl> {
+        let mut order = Vec::new();
+        let mut by_host: HashMap<String, VecDeque<FrontierUrl>> = HashMap::new();
+
+        for entry in urls {
+            let host = Url::parse(&entry.url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_default();
+            if !by_host.contains_key(&host) {
+                order.push(host.clone());
+            }
+            by_host.entry(host).or_default().push_back(entry);
         }
+
+        let mut interleaved = Vec::new();
+        while !by_host.is_empty() {
+            for host in &order {
+                if let Some(queue) = by_host.get_mut(host) {
+                    if let Some(entry) = queue.pop_front() {
+                        interleaved.push(entry);
+                    }
+                    if queue.is_empty() {
+                        by_host.remove(host);
+                    }
+                }
+            }
+        }
+
+        interleaved
     }
 
-    async fn fetch_pending_urls(&self, limit: i32) -> Result<Vec<(i32, String, i32)>> {
+    async fn fetch_pending_urls(&self, limit: i32) -> Result<Vec<FrontierUrl>> {
+        // SKIP LOCKED lets concurrent workers claim disjoint rows instead of racing for the same ones.
+        // Each host contributes at most as many rows as it may fetch at once, so a host with
+        // a long queue (or a long Crawl-delay) cannot fill the batch and hold up the next one
         let rows = sqlx::query(
             "UPDATE urls 
-             SET status = 'processing' 
+             SET status = 'processing',
+                 leased_until = NOW() + make_interval(secs => $2),
+                 worker_id = $3
              WHERE id IN (
                  SELECT id FROM urls 
                  WHERE status = 'pending' 
+                   AND id IN (
+                       SELECT id FROM (
+                           SELECT id, ROW_NUMBER() OVER (PARTITION BY host ORDER BY priority DESC, created_at ASC) AS host_rank 
+                           FROM urls 
+                           WHERE status = 'pending' 
+                             AND (next_attempt_at IS NULL OR next_attempt_at <= NOW())
+                       ) ranked 
+                       WHERE host_rank <= $4
+                   )
                  ORDER BY priority DESC, created_at ASC 
                  LIMIT $1
+                 FOR UPDATE SKIP LOCKED
              )
-             RETURNING id, url, depth"
+             RETURNING id, url, depth, error_count, index_name, content_hash, recrawl_interval_secs,
+                       etag, last_modified, document_id, duplicate_of"
         )
         .bind(limit)
+        .bind(self.config.lease_secs as f64)
+        .bind(&self.config.worker_id)
+        .bind(self.config.max_per_host.clamp(1, i32::MAX as usize) as i64)
         .fetch_all(&self.pool)
         .await?;
 
         let urls = rows
             .into_iter()
-            .map(|row| {
-                let id: i32 = row.get("id");
-                let url: String = row.get("url");
-                let depth: i32 = row.get("depth");
-                (id, url, depth)
+            .map(|row| FrontierUrl {
+                id: row.get("id"),
+                url: row.get("url"),
+                depth: row.get("depth"),
+                error_count: row.get("error_count"),
+                index_name: row.get("index_name"),
+                content_hash: row.get("content_hash"),
+                recrawl_interval_secs: row.get("recrawl_interval_secs"),
+                validators: Validators {
+                    etag: row.get("etag"),
+                    last_modified: row.get("last_modified"),
+                },
+                document_id: row.get("document_id"),
+                duplicate_of: row.get("duplicate_of"),
             })
             .collect();
 
         Ok(urls)
     }
 
-    async fn process_url(
-        id: i32,
-        url: &str,
-        depth: i32,
-        fetcher: &Fetcher,
-        parser: &Parser,
-        indexer: &IndexerClient,
-        pool: &PgPool,
-        max_depth: i32,
-    ) -> Result<()> {
-        debug!("Processing URL (depth={}): {}", depth, url);
+    async fn recover_stale_leases(&self) -> Result<u64> {
+        // Expired leases, rows claimed before leases existed, and our own rows from a previous run
+        let result = sqlx::query(
+            "UPDATE urls 
+             SET status = 'pending', leased_until = NULL, worker_id = NULL 
+             WHERE status IN ('processing', 'indexing') 
+               AND (leased_until IS NULL OR leased_until < NOW() OR worker_id = $1)"
+        )
+        .bind(&self.config.worker_id)
+        .execute(&self.pool)
+        .await?;
 
-        // Fetch HTML
-        let html = match fetcher.fetch(url).await {
-            Ok(html) => html,
+        Ok(result.rows_affected())
+    }
+
+    async fn reap_expired_leases(pool: &PgPool) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE urls 
+             SET status = 'pending', leased_until = NULL, worker_id = NULL 
+             WHERE status IN ('processing', 'indexing') AND leased_until < NOW()"
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn requeue_due_urls(pool: &PgPool) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE urls 
+             SET status = 'pending', next_crawl_at = NULL, worker_id = NULL 
+             WHERE id IN (
+                 SELECT id FROM urls 
+                 WHERE status IN ('completed', 'removed', 'duplicate') AND next_crawl_at <= NOW() 
+                 ORDER BY next_crawl_at 
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )"
+        )
+        .bind(RECRAWL_BATCH_SIZE)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn process_url(&self, entry: &FrontierUrl) -> Result<()> {
+        debug!("Processing URL (depth={}): {}", entry.depth, entry.url);
+
+        match self.crawl_url(entry).await {
+            // Completed once the search backend confirms the document
+            Ok(PageOutcome::Indexed(page)) => self.mark_url_indexing(entry, &page).await,
+            // Nothing for the search backend to confirm
+            Ok(PageOutcome::Collapsed(page)) => {
+                debug!("Collapsed {} into near-duplicate URL {:?}", entry.url, page.duplicate_of);
+                self.mark_url_indexing(entry, &page).await?;
+                self.mark_urls_completed(&[entry.id]).await
+            }
+            Ok(PageOutcome::Duplicate { of, simhash, content_hash }) => {
+                debug!("Not indexing {}: near-duplicate of URL {}", entry.url, of);
+                self.mark_url_duplicate(entry, of, simhash, &content_hash).await
+            }
+            Ok(PageOutcome::Unchanged(validators)) => {
+                debug!("Unchanged since last crawl: {}", entry.url);
+                self.stats.record_unchanged();
+                self.mark_url_unchanged(entry, validators.as_ref()).await
+            }
+            Ok(PageOutcome::NoIndex) => {
+                debug!("Not indexing {}: noindex", entry.url);
+                self.remove_page(entry).await
+            }
+            Err(CrawlError::Fetch(FetchError::RobotsBlocked)) => {
+                debug!("Skipping {}: disallowed by robots.txt", entry.url);
+                self.mark_url_blocked(entry.id).await
+            }
+            // A page we indexed before is gone, or still gone on the recheck after removal
+            // (removing keeps the content hash)
+            Err(CrawlError::Fetch(FetchError::Status { status, .. }))
+                if (entry.index_name.is_some() || entry.content_hash.is_some())
+                    && matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE) =>
+            {
+                info!("Removing {} from the index: HTTP {}", entry.url, status);
+                self.remove_page(entry).await
+            }
             Err(e) => {
-                Self::mark_url_failed(pool, id).await?;
-                return Err(e);
+                self.mark_url_failed(entry, &e).await?;
+                Err(e.into())
+            }
+        }
+    }
+
+    // Deletes the page's document, if it has one, and records the URL as removed
+    async fn remove_page(&self, entry: &FrontierUrl) -> Result<()> {
+        if let Some(index) = Self::own_document_index(entry) {
+            if let Err(e) = self.backend.delete(index, &generate_id(&entry.url)).await {
+                self.mark_url_failed(entry, &e).await?;
+                return Err(e.into());
+            }
+        }
+
+        self.mark_url_removed(entry.id).await
+    }
+
+    // Index holding a document under the page's own id. A page indexed under its
+    // rel=canonical URL or collapsed into a near-duplicate shares another page's document,
+    // which is not its to delete
+    fn own_document_index(entry: &FrontierUrl) -> Option<&str> {
+        let own = entry.document_id.as_deref() == Some(generate_id(&entry.url).as_str());
+        entry.index_name.as_deref().filter(|_| own)
+    }
+
+    // The earliest crawled page whose content is within MAX_DISTANCE bits of `hash`
+    async fn find_near_duplicate(&self, id: i32, hash: u64) -> Result<Option<NearDuplicate>, CrawlError> {
+        let [band0, band1, band2, band3] = simhash::bands(hash);
+
+        // The bands narrow the candidates through their indexes, the distance is checked
+        // before picking one so a common band value cannot crowd out the real neighbor
+        let row = sqlx::query(
+            "SELECT id, url, simhash, index_name, document_id FROM urls 
+             WHERE id < $1 
+               AND duplicate_of IS NULL 
+               AND status IN ('indexing', 'completed') 
+               AND (simhash_band0 = $2 OR simhash_band1 = $3 OR simhash_band2 = $4 OR simhash_band3 = $5)
+               AND bit_count((simhash # $6)::bit(64)) <= $7
+             ORDER BY id 
+             LIMIT 1"
+        )
+        .bind(id)
+        .bind(band0)
+        .bind(band1)
+        .bind(band2)
+        .bind(band3)
+        .bind(hash as i64)
+        .bind(simhash::MAX_DISTANCE as i32)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            let duplicate = NearDuplicate {
+                id: row.get("id"),
+                index: row.get("index_name"),
+                document_id: row
+                    .get::<Option<String>, _>("document_id")
+                    .unwrap_or_else(|| generate_id(row.get("url"))),
+            };
+            let bits = simhash::distance(row.get::<i64, _>("simhash") as u64, hash);
+            debug!("URL {} is {} bits from URL {}", id, bits, duplicate.id);
+            duplicate
+        }))
+    }
+
+    async fn crawl_url(&self, entry: &FrontierUrl) -> Result<PageOutcome, CrawlError> {
+        let url = entry.url.as_str();
+
+        // Fetch HTML, conditionally when there is an indexed copy to fall back on. A
+        // collapsed page is fetched in full, the page it duplicates may have changed since
+        let validators = match (&entry.index_name, entry.duplicate_of) {
+            (Some(_), None) => entry.validators.clone(),
+            _ => Validators::default(),
+        };
+        let page = match self.fetcher.fetch(url, &validators).await? {
+            FetchOutcome::Modified(page) => page,
+            FetchOutcome::NotModified => {
+                self.stats.record_crawled();
+                return Ok(PageOutcome::Unchanged(None));
             }
         };
+        self.stats.record_crawled();
+        self.stats.record_transfer(page.transfer);
 
         // Parse content
-        let parsed = match parser.parse(&html, url) {
-            Ok(parsed) => parsed,
-            Err(e) => {
-                Self::mark_url_failed(pool, id).await?;
-                return Err(e);
+        let parsed = self.parser.parse(&page.html, url)?;
+        let page_url = Url::parse(url)?;
+
+        // Meta robots and X-Robots-Tag both count
+        let robots = parsed.robots.merge(page.robots);
+
+        // Add new links to queue (if not at max depth), noindex pages are still followed
+        if entry.depth < self.config.max_depth && !robots.nofollow {
+            self.add_new_urls(&parsed.links, entry.depth + 1, Some(&page_url)).await?;
+        }
+
+        // Feeds are polled whatever the page's depth, they are how new articles show up
+        if !robots.nofollow && !parsed.feeds.is_empty() {
+            self.add_feeds(&parsed.feeds, &page_url).await?;
+        }
+
+        if robots.noindex {
+            return Ok(PageOutcome::NoIndex);
+        }
+
+        // A page naming another URL on the same host as canonical is indexed as that URL,
+        // which is queued so it gets crawled in its own right. Other hosts are ignored so a
+        // page cannot overwrite someone else's document
+        let canonical = parsed
+            .canonical
+            .as_deref()
+            .and_then(|canonical| self.config.normalizer.normalize(canonical))
+            .filter(|canonical| Some(canonical.as_str()) != self.config.normalizer.normalize(url).as_deref())
+            .filter(|canonical| {
+                Url::parse(canonical).is_ok_and(|canonical| canonical.host_str() == page_url.host_str())
+            });
+        if let Some(canonical) = &canonical {
+            debug!("Indexing {} as its canonical URL {}", url, canonical);
+            self.add_new_urls(std::slice::from_ref(canonical), entry.depth, Some(&page_url)).await?;
+        }
+
+        // Same content in the same index as last time, nothing to write
+        let index = self.config.router.route(&page_url, parsed.language.as_deref(), &page.content_type);
+        let content_hash = parsed.content_hash();
+        if entry.content_hash.as_deref() == Some(content_hash.as_str())
+            && entry.index_name.as_deref() == Some(index)
+            && entry.duplicate_of.is_none()
+        {
+            return Ok(PageOutcome::Unchanged(Some(page.validators)));
+        }
+
+        // Look for an earlier page with nearly the same text
+        let fingerprint = simhash::simhash(&parsed.content);
+        let duplicate = match (self.config.near_duplicates, fingerprint) {
+            (NearDuplicatePolicy::Off, _) | (_, None) => None,
+            (_, Some(hash)) => self.find_near_duplicate(entry.id, hash).await?,
+        };
+
+        let (index, document_id) = match (duplicate.as_ref(), self.config.near_duplicates) {
+            (Some(original), NearDuplicatePolicy::Skip) => {
+                if let Some(previous) = Self::own_document_index(entry) {
+                    if let Err(e) = self.backend.delete(previous, &generate_id(url)).await {
+                        warn!("Failed to remove {} from {}: {}", url, previous, e);
+                    }
+                }
+                return Ok(PageOutcome::Duplicate {
+                    of: original.id,
+                    simhash: fingerprint.unwrap_or_default(),
+                    content_hash,
+                });
+            }
+            // The earlier page's document is left as it is, with its own URL and text
+            (Some(original), _) => (
+                original.index.as_deref().unwrap_or(index),
+                original.document_id.clone(),
+            ),
+            (None, _) => {
+                let document = Document::new(canonical.as_deref().unwrap_or(url), &parsed);
+                let document_id = document.id.clone();
+                if let Err(e) = self.backend.index(index, entry.id, document).await {
+                    error!("Failed to index {}: {}", url, e);
+                    return Err(e);
+                }
+                (index, document_id)
             }
         };
 
-        // Index document
-        if let Err(e) = indexer.index_document(url, &parsed.name, &parsed.title, &parsed.description, &parsed.icon, &parsed.content).await {
-            error!("Failed to index {}: {}", url, e);
-            Self::mark_url_failed(pool, id).await?;
-            return Err(e);
+        // The routing rules, the canonical URL or collapsing changed the document since the
+        // last crawl, don't leave the old one behind
+        if let Some(previous) = Self::own_document_index(entry) {
+            let own_id = generate_id(url);
+            if previous != index || document_id != own_id {
+                if let Err(e) = self.backend.delete(previous, &own_id).await {
+                    warn!("Failed to remove {} from {}: {}", url, previous, e);
+                }
+            }
         }
 
-        // Add new links to queue (if not at max depth)
-        if depth < max_depth {
-            Self::add_new_urls(pool, &parsed.links, depth + 1).await?;
-        }
+        let page = IndexedPage {
+            index: index.to_string(),
+            document_id,
+            content_hash,
+            validators: page.validators,
+            simhash: fingerprint,
+            duplicate_of: duplicate.as_ref().map(|original| original.id),
+        };
+        Ok(match duplicate {
+            Some(_) => PageOutcome::Collapsed(page),
+            None => PageOutcome::Indexed(page),
+        })
+    }
 
-        // Mark as completed
-        Self::mark_url_completed(pool, id).await?;
+    // Status updates only apply while we still own the row, so a worker whose lease
+    // was reaped cannot overwrite the result of whoever claimed the URL next. The lease
+    // starts over, giving the search backend a full period to confirm the document
+    async fn mark_url_indexing(&self, entry: &FrontierUrl, page: &IndexedPage) -> Result<()> {
+        let bands = page.simhash.map(simhash::bands);
+        let changed = entry.content_hash.as_deref() != Some(page.content_hash.as_str());
+        let interval = self.next_recrawl_interval(entry, changed);
+
+        sqlx::query(
+            "UPDATE urls 
+             SET status = 'indexing',
+                 last_crawled = NOW(),
+                 index_name = $3,
+                 content_hash = $4,
+                 recrawl_interval_secs = $5,
+                 etag = $6,
+                 last_modified = $7,
+                 simhash = $8,
+                 simhash_band0 = $9,
+                 simhash_band1 = $10,
+                 simhash_band2 = $11,
+                 simhash_band3 = $12,
+                 duplicate_of = $13,
+                 document_id = $14,
+                 leased_until = NOW() + make_interval(secs => $15)
+             WHERE id = $1 AND worker_id = $2"
+        )
+        .bind(entry.id)
+        .bind(&self.config.worker_id)
+        .bind(&page.index)
+        .bind(&page.content_hash)
+        .bind(interval)
+        .bind(&page.validators.etag)
+        .bind(&page.validators.last_modified)
+        .bind(page.simhash.map(|hash| hash as i64))
+        .bind(bands.map(|bands| bands[0]))
+        .bind(bands.map(|bands| bands[1]))
+        .bind(bands.map(|bands| bands[2]))
+        .bind(bands.map(|bands| bands[3]))
+        .bind(page.duplicate_of)
+        .bind(&page.document_id)
+        .bind(self.config.lease_secs as f64)
+        .execute(&self.pool)
+        .await?;
 
         Ok(())
     }
 
-    async fn mark_url_completed(pool: &PgPool, id: i32) -> Result<()> {
+    // Nothing to reindex, so the URL completes straight away
+    async fn mark_url_unchanged(&self, entry: &FrontierUrl, validators: Option<&Validators>) -> Result<()> {
+        let interval = self.next_recrawl_interval(entry, false);
+
         sqlx::query(
-            "UPDATE urls SET status = 'completed', last_crawled = NOW() WHERE id = $1"
+            "UPDATE urls 
+             SET status = 'completed',
+                 last_crawled = NOW(),
+                 error_count = 0,
+                 last_error_kind = NULL,
+                 last_error = NULL,
+                 next_attempt_at = NULL,
+                 leased_until = NULL,
+                 recrawl_interval_secs = $3,
+                 next_crawl_at = NOW() + make_interval(secs => $3),
+                 etag = CASE WHEN $4 THEN $5 ELSE etag END,
+                 last_modified = CASE WHEN $4 THEN $6 ELSE last_modified END
+             WHERE id = $1 AND worker_id = $2"
         )
-        .bind(id)
-        .execute(pool)
+        .bind(entry.id)
+        .bind(&self.config.worker_id)
+        .bind(interval)
+        .bind(validators.is_some())
+        .bind(validators.and_then(|v| v.etag.as_deref()))
+        .bind(validators.and_then(|v| v.last_modified.as_deref()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    fn next_recrawl_interval(&self, entry: &FrontierUrl, changed: bool) -> i32 {
+        let previous = entry
+            .recrawl_interval_secs
+            .map(|secs| Duration::from_secs(secs.max(0) as u64));
+        let interval = match previous {
+            // Nothing to compare on a first visit, keep what the sitemap suggested
+            Some(previous) if entry.content_hash.is_none() => self.config.recrawl.clamp(previous),
+            _ => self.config.recrawl.next_interval(previous, changed),
+        };
+
+        interval.as_secs().min(i32::MAX as u64) as i32
+    }
+
+    async fn mark_urls_completed(&self, ids: &[i32]) -> Result<()> {
+        sqlx::query(
+            "UPDATE urls 
+             SET status = 'completed', 
+                 error_count = 0,
+                 last_error_kind = NULL,
+                 last_error = NULL,
+                 next_attempt_at = NULL,
+                 leased_until = NULL,
+                 next_crawl_at = NOW() + make_interval(secs => recrawl_interval_secs)
+             WHERE id = ANY($1) AND worker_id = $2"
+        )
+        .bind(ids)
+        .bind(&self.config.worker_id)
+        .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    async fn mark_url_failed(pool: &PgPool, id: i32) -> Result<()> {
+    async fn mark_urls_index_failed(&self, ids: &[i32], error: &CrawlError) -> Result<()> {
+        // The search backend being unreachable is retried like any other transient error,
+        // a document it rejected is not
+        // Validators and the hash are dropped so the retry fetches and indexes the page again
+        // rather than taking it for unchanged
+        let retry_after = error
+            .kind()
+            .is_retryable()
+            .then_some(self.config.retry.base_delay.as_secs_f64());
+
         sqlx::query(
             "UPDATE urls 
-             SET status = 'failed', 
+             SET status = CASE 
+                     WHEN $3::float8 IS NOT NULL AND error_count + 1 < $4 THEN 'pending' 
+                     ELSE 'index_failed' 
+                 END,
                  error_count = error_count + 1,
-                 last_crawled = NOW() 
-             WHERE id = $1"
+                 last_error_kind = $5,
+                 last_error = $6,
+                 next_attempt_at = NOW() + make_interval(secs => COALESCE($3::float8, 0)),
+                 leased_until = NULL,
+                 etag = NULL,
+                 last_modified = NULL,
+                 content_hash = NULL
+             WHERE id = ANY($1) AND worker_id = $2"
+        )
+        .bind(ids)
+        .bind(&self.config.worker_id)
+        .bind(retry_after)
+        .bind(self.config.retry.max_errors)
+        .bind(error.kind().as_str())
+        .bind(error.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_url_blocked(&self, id: i32) -> Result<()> {
+        sqlx::query(
+            "UPDATE urls SET status = 'robots_blocked', leased_until = NULL, last_crawled = NOW() WHERE id = $1 AND worker_id = $2"
         )
         .bind(id)
-        .execute(pool)
+        .bind(&self.config.worker_id)
+        .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    async fn add_new_urls(pool: &PgPool, urls: &[String], depth: i32) -> Result<()> {
+    // Recrawled like a completed page, the page it duplicates may change or disappear
+    async fn mark_url_duplicate(&self, entry: &FrontierUrl, of: i32, simhash: u64, content_hash: &str) -> Result<()> {
+        let changed = entry.content_hash.as_deref() != Some(content_hash);
+        let interval = self.next_recrawl_interval(entry, changed);
+
+        sqlx::query(
+            "UPDATE urls 
+             SET status = 'duplicate',
+                 duplicate_of = $3,
+                 simhash = $4,
+                 index_name = NULL,
+                 document_id = NULL,
+                 content_hash = $5,
+                 etag = NULL,
+                 last_modified = NULL,
+                 error_count = 0,
+                 last_error_kind = NULL,
+                 last_error = NULL,
+                 next_attempt_at = NULL,
+                 leased_until = NULL,
+                 last_crawled = NOW(),
+                 recrawl_interval_secs = $6,
+                 next_crawl_at = NOW() + make_interval(secs => $6)
+             WHERE id = $1 AND worker_id = $2"
+        )
+        .bind(entry.id)
+        .bind(&self.config.worker_id)
+        .bind(of)
+        .bind(simhash as i64)
+        .bind(content_hash)
+        .bind(interval)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Checked again after the longest recrawl interval, in case the 404 was temporary or a
+    // noindex page has new links to follow
+    async fn mark_url_removed(&self, id: i32) -> Result<()> {
+        sqlx::query(
+            "UPDATE urls 
+             SET status = 'removed',
+                 index_name = NULL,
+                 document_id = NULL,
+                 error_count = 0,
+                 last_error_kind = NULL,
+                 last_error = NULL,
+                 next_attempt_at = NULL,
+                 leased_until = NULL,
+                 last_crawled = NOW(),
+                 next_crawl_at = NOW() + make_interval(secs => $3)
+             WHERE id = $1 AND worker_id = $2"
+        )
+        .bind(id)
+        .bind(&self.config.worker_id)
+        .bind(self.config.recrawl.max_interval.as_secs_f64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_url_failed(&self, entry: &FrontierUrl, error: &CrawlError) -> Result<()> {
+        self.stats.record_error();
+        // A host-level failure says nothing about the URL, so it isn't counted against it
+        let increment = i32::from(!error.kind().is_host_level());
+        let error_count = entry.error_count + increment;
+
+        // Transient failures go back to the frontier with a delay, the rest are terminal
+        let Some(delay) = self.config.retry.next_attempt(error, error_count) else {
+            let status = match error.kind() {
+                ErrorKind::TooLarge => "too_large",
+                _ => "failed",
+            };
+
+            sqlx::query(
+                "UPDATE urls 
+                 SET status = $5, 
+                     error_count = error_count + $6,
+                     last_error_kind = $3,
+                     last_error = $4,
+                     next_attempt_at = NULL,
+                     leased_until = NULL,
+                     last_crawled = NOW() 
+                 WHERE id = $1 AND worker_id = $2"
+            )
+            .bind(entry.id)
+            .bind(&self.config.worker_id)
+            .bind(error.kind().as_str())
+            .bind(error.to_string())
+            .bind(status)
+            .bind(increment)
+            .execute(&self.pool)
+            .await?;
+
+            return Ok(());
+        };
+
+        debug!("Retrying {} in {}s (attempt {})", entry.url, delay.as_secs(), error_count + 1);
+
+        sqlx::query(
+            "UPDATE urls 
+             SET status = 'pending', 
+                 error_count = error_count + $6,
+                 last_error_kind = $4,
+                 last_error = $5,
+                 next_attempt_at = NOW() + make_interval(secs => $3),
+                 leased_until = NULL,
+                 last_crawled = NOW() 
+             WHERE id = $1 AND worker_id = $2"
+        )
+        .bind(entry.id)
+        .bind(&self.config.worker_id)
+        .bind(delay.as_secs_f64())
+        .bind(error.kind().as_str())
+        .bind(error.to_string())
+        .bind(increment)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // `source` is the page the URLs were found on; seeds have none and skip the scope rules
+    async fn add_new_urls(&self, urls: &[String], depth: i32, source: Option<&Url>) -> Result<(), CrawlError> {
+        let urls = urls
+            .iter()
+            .map(|url| NewUrl {
+                url: url.clone(),
+                depth,
+                priority: 10 - depth, // Higher priority for shallower pages
+                recrawl_interval_secs: None,
+                lastmod: None,
+            })
+            .collect();
+
+        let count = self.enqueue_urls(urls, source).await?;
+        debug!("Added {} new URLs at depth {}", count, depth);
+
+        Ok(())
+    }
+
+    // Normalizes, filters and inserts URLs, returning how many were new
+    async fn enqueue_urls(&self, urls: Vec<NewUrl>, source: Option<&Url>) -> Result<usize, CrawlError> {
+        // Spell every URL one way so variants of a page don't become separate rows
+        let mut urls = urls
+            .into_iter()
+            .filter_map(|new_url| {
+                let url = self.config.normalizer.normalize(&new_url.url)?;
+                Some(NewUrl { url, ..new_url })
+            })
+            .filter(|new_url| source.is_none_or(|source| self.config.scope.allows(&new_url.url, source)))
+            .collect::<Vec<_>>();
+        urls.sort_by(|a, b| a.url.cmp(&b.url));
+        urls.dedup_by(|a, b| a.url == b.url);
+
         if urls.is_empty() {
+            return Ok(0);
+        }
+
+        let hosts = urls
+            .iter()
+            .map(|new_url| {
+                Url::parse(&new_url.url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+
+        // Rows each host already has, to enforce the per-host limit
+        let limit_hosts = source.is_some() && self.config.scope.max_urls_per_host > 0;
+        let mut host_counts: HashMap<String, i64> = HashMap::new();
+        if limit_hosts {
+            let rows = sqlx::query("SELECT host, COUNT(*) AS count FROM urls WHERE host = ANY($1) GROUP BY host")
+                .bind(&hosts)
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                host_counts.insert(row.get("host"), row.get("count"));
+            }
+        }
+
+        // Insert new URLs; a known one is only touched when a sitemap says it changed
+        let mut inserted = 0;
+        for (new_url, host) in urls.iter().zip(&hosts) {
+            if limit_hosts && host_counts.get(host).is_some_and(|count| *count >= self.config.scope.max_urls_per_host) {
+                continue;
+            }
+
+            // xmax is 0 only for a row this statement inserted
+            let result = sqlx::query(
+                "INSERT INTO urls (url, host, depth, priority, recrawl_interval_secs) 
+                 VALUES ($1, $2, $3, $4, $5) 
+                 ON CONFLICT (url) DO UPDATE SET next_crawl_at = NOW() 
+                 WHERE urls.status = 'completed' AND urls.last_crawled < to_timestamp($6::float8) 
+                 RETURNING (xmax = 0) AS inserted"
+            )
+            .bind(&new_url.url)
+            .bind(host)
+            .bind(new_url.depth)
+            .bind(new_url.priority)
+            .bind(new_url.recrawl_interval_secs)
+            .bind(new_url.lastmod.map(|lastmod| lastmod.timestamp() as f64))
+            .fetch_optional(&self.pool)
+            .await;
+
+            // Only new rows count toward the host's limit, not URLs it already had
+            if let Ok(Some(row)) = result {
+                if row.get::<bool, _>("inserted") {
+                    inserted += 1;
+                    *host_counts.entry(host.clone()).or_default() += 1;
+                }
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    // Records sitemaps found in robots.txt files and fetches the ones that are due
+    async fn sitemap_loop(&self) -> Result<()> {
+        loop {
+            if let Err(e) = self.sync_sitemaps().await {
+                warn!("Failed to sync sitemaps: {}", e);
+            }
+            sleep(SITEMAP_CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn sync_sitemaps(&self) -> Result<()> {
+        for (url, source, site) in self.fetcher.take_sitemaps() {
+            self.add_sitemap(&url, source, &site, None).await?;
+        }
+
+        for sitemap in self.fetch_due_sitemaps().await? {
+            match self.ingest_sitemap(&sitemap).await {
+                Ok(count) => {
+                    debug!("Read {} entries from sitemap {}", count, sitemap.url);
+                    self.mark_sitemap_fetched(sitemap.id, count, None).await?;
+                }
+                Err(e) => {
+                    debug!("Failed to read sitemap {}: {}", sitemap.url, e);
+                    self.mark_sitemap_fetched(sitemap.id, 0, Some(&e.to_string())).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // A known sitemap whose lastmod in an index is newer than our last fetch is due again
+    async fn add_sitemap(
+        &self,
+        url: &str,
+        source: SitemapSource,
+        site: &str,
+        lastmod: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let Some(url) = self.config.normalizer.normalize(url) else {
             return Ok(());
+        };
+
+        sqlx::query(
+            "INSERT INTO sitemaps (url, source, site) 
+             VALUES ($1, $2, $3) 
+             ON CONFLICT (url) DO UPDATE SET next_fetch_at = NOW() 
+             WHERE sitemaps.last_fetched < to_timestamp($4::float8)"
+        )
+        .bind(&url)
+        .bind(source.as_str())
+        .bind(site)
+        .bind(lastmod.map(|lastmod| lastmod.timestamp() as f64))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn fetch_due_sitemaps(&self) -> Result<Vec<DueSitemap>> {
+        // Pushing next_fetch_at out by a lease keeps other workers off the claimed rows
+        let rows = sqlx::query(
+            "UPDATE sitemaps 
+             SET next_fetch_at = NOW() + make_interval(secs => $2) 
+             WHERE id IN (
+                 SELECT id FROM sitemaps 
+                 WHERE next_fetch_at <= NOW() 
+                 ORDER BY next_fetch_at 
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, url, site"
+        )
+        .bind(SITEMAP_BATCH_SIZE)
+        .bind(self.config.lease_secs as f64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DueSitemap {
+                id: row.get("id"),
+                url: row.get("url"),
+                site: row.get("site"),
+            })
+            .collect())
+    }
+
+    // Queues the pages or child sitemaps a sitemap lists, returning how many were kept.
+    // A robots.txt may declare a sitemap hosted elsewhere, so pages must be on the host of
+    // the site that declared it, and child sitemaps on that host or the index's own
+    async fn ingest_sitemap(&self, sitemap: &DueSitemap) -> Result<usize> {
+        let sitemap_url = Url::parse(&sitemap.url)?;
+        let site_url = Url::parse(&sitemap.site)?;
+        let (body, transfer) = self.fetcher.fetch_bytes(&sitemap.url).await?;
+        self.stats.record_transfer(transfer);
+        let on_host = |url: &str, host: &Url| Url::parse(url).is_ok_and(|url| url.host_str() == host.host_str());
+
+        match sitemap::parse(&body, self.fetcher.limits().max_decompressed_bytes)? {
+            Sitemap::Index(children) => {
+                let children = children
+                    .into_iter()
+                    .filter(|(url, _)| on_host(url, &site_url) || on_host(url, &sitemap_url))
+                    .collect::<Vec<_>>();
+                for (url, lastmod) in &children {
+                    self.add_sitemap(url, SitemapSource::Index, &sitemap.site, *lastmod).await?;
+                }
+                Ok(children.len())
+            }
+            Sitemap::UrlSet(entries) => {
+                let entries = entries
+                    .into_iter()
+                    .filter(|entry| on_host(&entry.loc, &site_url))
+                    .collect::<Vec<_>>();
+                let count = entries.len();
+                let urls = entries
+                    .into_iter()
+                    .map(|entry| NewUrl {
+                        priority: entry.frontier_priority(),
+                        recrawl_interval_secs: entry.changefreq.map(|changefreq| {
+                            let interval = self.config.recrawl.clamp(changefreq.interval());
+                            interval.as_secs().min(i32::MAX as u64) as i32
+                        }),
+                        lastmod: entry.lastmod,
+                        url: entry.loc,
+                        // Pages a site lists are entry points, like seeds
+                        depth: 0,
+                    })
+                    .collect();
+                self.enqueue_urls(urls, Some(&site_url)).await?;
+                Ok(count)
+            }
         }
+    }
 
-        // Insert new URLs (ignore duplicates)
-        for url in urls {
-            let _ = sqlx::query(
-                "INSERT INTO urls (url, depth, priority) 
+    // Feeds are subject to the same scope rules as links
+    async fn add_feeds(&self, feeds: &[String], source: &Url) -> Result<(), CrawlError> {
+        for url in feeds {
+            let Some(url) = self.config.normalizer.normalize(url) else {
+                continue;
+            };
+            if !self.config.scope.allows(&url, source) {
+                continue;
+            }
+
+            sqlx::query(
+                "INSERT INTO feeds (url, found_on, poll_interval_secs) 
                  VALUES ($1, $2, $3) 
                  ON CONFLICT (url) DO NOTHING"
             )
-            .bind(url)
-            .bind(depth)
-            .bind(10 - depth) // Higher priority for shallower pages
-            .execute(pool)
-            .await;
+            .bind(&url)
+            .bind(source.as_str())
+            .bind(self.config.feed_poll.min_interval.as_secs().min(i32::MAX as u64) as i32)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    // Polls due feeds and queues their new items
+    async fn feed_loop(&self) -> Result<()> {
+        loop {
+            if let Err(e) = self.poll_feeds().await {
+                warn!("Failed to poll feeds: {}", e);
+            }
+            sleep(FEED_CHECK_INTERVAL).await;
         }
+    }
 
-        debug!("Added {} new URLs at depth {}", urls.len(), depth);
+    async fn poll_feeds(&self) -> Result<()> {
+        // Pushing next_poll_at out by a lease keeps other workers off the claimed rows
+        let rows = sqlx::query(
+            "UPDATE feeds 
+             SET next_poll_at = NOW() + make_interval(secs => $2) 
+             WHERE id IN (
+                 SELECT id FROM feeds 
+                 WHERE next_poll_at <= NOW() 
+                 ORDER BY next_poll_at 
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, url, poll_interval_secs"
+        )
+        .bind(FEED_BATCH_SIZE)
+        .bind(self.config.lease_secs as f64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let feeds = rows.into_iter().map(|row| DueFeed {
+            id: row.get("id"),
+            url: row.get("url"),
+            poll_interval_secs: row.get("poll_interval_secs"),
+        });
+
+        for due in feeds {
+            match self.poll_feed(&due).await {
+                Ok(new_items) => {
+                    if new_items > 0 {
+                        info!("Queued {} new items from feed {}", new_items, due.url);
+                    }
+                    self.mark_feed_polled(&due, new_items, None).await?;
+                }
+                Err(e) => {
+                    debug!("Failed to poll feed {}: {}", due.url, e);
+                    self.mark_feed_polled(&due, 0, Some(&e.to_string())).await?;
+                }
+            }
+        }
 
         Ok(())
     }
+
+    // Queues the feed's items, returning how many were not in the frontier yet
+    async fn poll_feed(&self, due: &DueFeed) -> Result<usize> {
+        let feed_url = Url::parse(&due.url)?;
+        let (body, transfer) = self.fetcher.fetch_bytes(&due.url).await?;
+        self.stats.record_transfer(transfer);
+
+        let urls = feed::parse(&body)?
+            .into_iter()
+            .filter_map(|item| {
+                // Relative links are resolved against the feed
+                let url = feed_url.join(&item.link).ok()?;
+                Some(NewUrl {
+                    url: url.to_string(),
+                    depth: 0,
+                    priority: FEED_ITEM_PRIORITY,
+                    recrawl_interval_secs: None,
+                    lastmod: item.updated,
+                })
+            })
+            .collect();
+
+        Ok(self.enqueue_urls(urls, Some(&feed_url)).await?)
+    }
+
+    // Feeds with new items are polled more often, quiet ones less
+    async fn mark_feed_polled(&self, due: &DueFeed, new_items: usize, error: Option<&str>) -> Result<()> {
+        let previous = Duration::from_secs(due.poll_interval_secs.max(0) as u64);
+        let interval = self.config.feed_poll.next_interval(Some(previous), new_items > 0);
+        let interval = interval.as_secs().min(i32::MAX as u64) as i32;
+
+        sqlx::query(
+            "UPDATE feeds 
+             SET last_polled = NOW(),
+                 poll_interval_secs = $2,
+                 next_poll_at = NOW() + make_interval(secs => $2),
+                 last_new_items = $3,
+                 error_count = CASE WHEN $4::text IS NULL THEN 0 ELSE error_count + 1 END,
+                 last_error = $4
+             WHERE id = $1"
+        )
+        .bind(due.id)
+        .bind(interval)
+        .bind(new_items as i32)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_sitemap_fetched(&self, id: i32, url_count: usize, error: Option<&str>) -> Result<()> {
+        sqlx::query(
+            "UPDATE sitemaps 
+             SET last_fetched = CASE WHEN $4::text IS NULL THEN NOW() ELSE last_fetched END,
+                 next_fetch_at = NOW() + make_interval(secs => $2),
+                 url_count = CASE WHEN $4::text IS NULL THEN $3 ELSE url_count END,
+                 error_count = CASE WHEN $4::text IS NULL THEN 0 ELSE error_count + 1 END,
+                 last_error = $4
+             WHERE id = $1"
+        )
+        .bind(id)
+        .bind(self.config.sitemap_interval.as_secs_f64())
+        .bind(url_count as i32)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // Render and Railway stop containers with SIGTERM
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_backend::MemoryBackend;
+    use crate::normalize::TrailingSlash;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use sqlx::Executor;
+    use std::str::FromStr;
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const WORKERS: usize = 4;
+    const PAGES: usize = 300;
+
+    // A throwaway schema in TEST_DATABASE_URL, dropped by drop_test_schema
+    async fn test_schema(name: &str) -> (PgPool, PgPool, String) {
+        let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let schema = format!("crawler_test_{}_{}", name, std::process::id());
+
+        let admin = PgPool::connect(&database_url).await.unwrap();
+        admin.execute(format!("DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}", schema).as_str())
+            .await
+            .unwrap();
+
+        let options = PgConnectOptions::from_str(&database_url)
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new().max_connections(20).connect_with(options).await.unwrap();
+        pool.execute(include_str!("../../database/schema.sql")).await.unwrap();
+        pool.execute("DELETE FROM urls").await.unwrap();
+
+        (admin, pool, schema)
+    }
+
+    async fn drop_test_schema(admin: PgPool, pool: PgPool, schema: String) {
+        pool.close().await;
+        admin.execute(format!("DROP SCHEMA {} CASCADE", schema).as_str()).await.unwrap();
+    }
+
+    // Plays the crawled site; documents go to an in-memory backend
+    async fn test_site() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(path("/robots.txt"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/page/[0-9]+$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(
+                    "<html><head><title>Test</title></head><body><p>Hello</p></body></html>",
+                    "text/html",
+                ),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    async fn insert_pages(pool: &PgPool, server: &MockServer, pages: usize) {
+        for i in 0..pages {
+            sqlx::query("INSERT INTO urls (url, depth) VALUES ($1, 0)")
+                .bind(format!("{}/page/{}", server.uri(), i))
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    // How many times each page was requested
+    async fn page_fetches(server: &MockServer) -> HashMap<String, usize> {
+        let mut fetches: HashMap<String, usize> = HashMap::new();
+        for request in server.received_requests().await.unwrap() {
+            if request.url.path().starts_with("/page/") {
+                *fetches.entry(request.url.path().to_string()).or_default() += 1;
+            }
+        }
+        fetches
+    }
+
+    struct MirroredArticle {
+        server: MockServer,
+        backend: Arc<MemoryBackend>,
+        crawler: Crawler,
+        original: String,
+        mirror: String,
+    }
+
+    // Serves one article at two URLs and crawls the original, then its mirror
+    async fn crawl_mirrored_article(pool: &PgPool, near_duplicates: NearDuplicatePolicy) -> MirroredArticle {
+        let server = test_site().await;
+        let article = (0..80).map(|i| format!("word{}", i)).collect::<Vec<_>>().join(" ");
+        Mock::given(method("GET"))
+            .and(path_regex("^/article/[a-z]+$"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(format!("<html><body><p>{}</p></body></html>", article), "text/html"),
+            )
+            .mount(&server)
+            .await;
+
+        let backend = Arc::new(MemoryBackend::default());
+        let config = CrawlerConfig {
+            near_duplicates,
+            ..test_config(0)
+        };
+        let crawler = Crawler::new(pool.clone(), backend.clone(), config);
+
+        let original = format!("{}/article/original", server.uri());
+        let mirror = format!("{}/article/mirror", server.uri());
+        for url in [&original, &mirror] {
+            sqlx::query("INSERT INTO urls (url, depth) VALUES ($1, 0)")
+                .bind(url)
+                .execute(pool)
+                .await
+                .unwrap();
+            crawler.crawl_batch().await.unwrap();
+            crawler.drain_index_tasks().await.unwrap();
+        }
+
+        MirroredArticle { server, backend, crawler, original, mirror }
+    }
+
+    fn test_config(worker: usize) -> CrawlerConfig {
+        CrawlerConfig {
+            concurrency: 20,
+            delay_ms: 0,
+            max_per_host: 20,
+            max_depth: 0,
+            body_limits: BodyLimits {
+                max_body_bytes: 1024 * 1024,
+                max_decompressed_bytes: 1024 * 1024,
+            },
+            worker_id: format!("worker-{}", worker),
+            lease_secs: 600,
+            retry: RetryPolicy {
+                max_errors: 5,
+                base_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(3600),
+            },
+            recrawl: RecrawlPolicy {
+                min_interval: Duration::from_secs(3600),
+                max_interval: Duration::from_secs(86400),
+            },
+            near_duplicates: NearDuplicatePolicy::Off,
+            normalizer: UrlNormalizer::new("", TrailingSlash::Keep),
+            scope: CrawlScope::default(),
+            seeds: Vec::new(),
+            router: IndexRouter::parse("web_pages".to_string(), "").unwrap(),
+            index_flush_interval: Duration::from_secs(1),
+            sitemap_interval: Duration::from_secs(24 * 60 * 60),
+            feed_poll: RecrawlPolicy {
+                min_interval: Duration::from_secs(5 * 60),
+                max_interval: Duration::from_secs(6 * 60 * 60),
+            },
+        }
+    }
+
+    // Runs against a throwaway schema in TEST_DATABASE_URL:
+    // TEST_DATABASE_URL=postgres://... cargo test -- --ignored
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn concurrent_workers_process_each_url_once() {
+        let (admin, pool, schema) = test_schema("workers").await;
+        let server = test_site().await;
+        insert_pages(&pool, &server, PAGES).await;
+
+        // One backend per worker, as each only settles outcomes for its own leases
+        let backends = (0..WORKERS)
+            .map(|_| Arc::new(MemoryBackend::default()))
+            .collect::<Vec<_>>();
+
+        let handles = (0..WORKERS)
+            .map(|worker| {
+                let crawler = Crawler::new(pool.clone(), backends[worker].clone(), test_config(worker));
+                tokio::spawn(async move {
+                    while crawler.crawl_batch().await.unwrap() > 0 {}
+                    crawler.drain_index_tasks().await.unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        // Every page was fetched exactly once
+        let fetches = page_fetches(&server).await;
+        assert_eq!(fetches.len(), PAGES);
+        assert!(fetches.values().all(|count| *count == 1));
+        let indexed: usize = backends.iter().map(|backend| backend.documents("web_pages").len()).sum();
+        assert_eq!(indexed, PAGES);
+
+        let rows = sqlx::query("SELECT status, worker_id FROM urls")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), PAGES);
+        for row in rows {
+            assert_eq!(row.get::<String, _>("status"), "completed");
+            assert!(row.get::<Option<String>, _>("worker_id").is_some());
+        }
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // A batch on one slow host takes several leases; its rows must not be reaped meanwhile
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn leases_outlive_a_slow_batch() {
+        const SLOW_PAGES: usize = 6;
+
+        let (admin, pool, schema) = test_schema("leases").await;
+        let server = test_site().await;
+        insert_pages(&pool, &server, SLOW_PAGES).await;
+
+        // One request a second against a 3s lease
+        let config = CrawlerConfig {
+            delay_ms: 1000,
+            lease_secs: 3,
+            ..test_config(0)
+        };
+        let crawler = Crawler::new(pool.clone(), Arc::new(MemoryBackend::default()), config);
+
+        let reaper_pool = pool.clone();
+        let reaper = tokio::spawn(async move {
+            loop {
+                Crawler::reap_expired_leases(&reaper_pool).await.unwrap();
+                sleep(Duration::from_millis(200)).await;
+            }
+        });
+
+        assert_eq!(crawler.crawl_batch().await.unwrap(), SLOW_PAGES);
+        crawler.drain_index_tasks().await.unwrap();
+        reaper.abort();
+
+        let fetches = page_fetches(&server).await;
+        assert_eq!(fetches.len(), SLOW_PAGES);
+        assert!(fetches.values().all(|count| *count == 1));
+
+        let completed: i64 = sqlx::query("SELECT COUNT(*) AS count FROM urls WHERE status = 'completed'")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get("count");
+        assert_eq!(completed, SLOW_PAGES as i64);
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // A 404 removes the page but it is checked again later, and stays removed while it is gone
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn removed_pages_are_rechecked() {
+        let (admin, pool, schema) = test_schema("removed").await;
+        let server = test_site().await;
+        insert_pages(&pool, &server, 1).await;
+
+        let backend = Arc::new(MemoryBackend::default());
+        let crawler = Crawler::new(pool.clone(), backend.clone(), test_config(0));
+        let status = || async {
+            sqlx::query("SELECT status, next_crawl_at IS NOT NULL AS scheduled FROM urls")
+                .fetch_one(&pool)
+                .await
+                .map(|row| (row.get::<String, _>("status"), row.get::<bool, _>("scheduled")))
+                .unwrap()
+        };
+        let recrawl = || async {
+            sqlx::query("UPDATE urls SET next_crawl_at = NOW() - INTERVAL '1 second'")
+                .execute(&pool)
+                .await
+                .unwrap();
+            assert_eq!(Crawler::requeue_due_urls(&pool).await.unwrap(), 1);
+            assert_eq!(crawler.crawl_batch().await.unwrap(), 1);
+            crawler.drain_index_tasks().await.unwrap();
+        };
+
+        crawler.crawl_batch().await.unwrap();
+        crawler.drain_index_tasks().await.unwrap();
+        assert_eq!(status().await, ("completed".to_string(), true));
+        assert_eq!(backend.documents("web_pages").len(), 1);
+
+        server.reset().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        recrawl().await;
+        assert_eq!(status().await, ("removed".to_string(), true));
+        assert!(backend.documents("web_pages").is_empty());
+
+        recrawl().await;
+        assert_eq!(status().await, ("removed".to_string(), true));
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // Under collapse a near-duplicate points at the earlier page's document without
+    // overwriting it
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn collapsed_duplicates_leave_the_original_document() {
+        let (admin, pool, schema) = test_schema("collapse").await;
+        let MirroredArticle { server: _server, backend, crawler: _, original, mirror } =
+            crawl_mirrored_article(&pool, NearDuplicatePolicy::Collapse).await;
+
+        let documents = backend.documents("web_pages");
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].url, original);
+
+        let row = sqlx::query(
+            "SELECT m.status, m.document_id = o.document_id AS same_document, m.duplicate_of = o.id AS points_at 
+             FROM urls m, urls o WHERE m.url = $1 AND o.url = $2"
+        )
+        .bind(&mirror)
+        .bind(&original)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(row.get::<String, _>("status"), "completed");
+        assert!(row.get::<bool, _>("same_document"));
+        assert!(row.get::<bool, _>("points_at"));
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // A skipped duplicate is recrawled and indexed once the page it duplicated is gone
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn skipped_duplicates_are_recrawled() {
+        let (admin, pool, schema) = test_schema("skip").await;
+        let MirroredArticle { server: _server, backend, crawler, original, mirror } =
+            crawl_mirrored_article(&pool, NearDuplicatePolicy::Skip).await;
+
+        let status = sqlx::query("SELECT status, next_crawl_at IS NOT NULL AS scheduled FROM urls WHERE url = $1")
+            .bind(&mirror)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status.get::<String, _>("status"), "duplicate");
+        assert!(status.get::<bool, _>("scheduled"));
+
+        // The original went away; the mirror is due
+        sqlx::query("UPDATE urls SET status = 'removed', next_crawl_at = NULL WHERE url = $1")
+            .bind(&original)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE urls SET next_crawl_at = NOW() - INTERVAL '1 second' WHERE url = $1")
+            .bind(&mirror)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(Crawler::requeue_due_urls(&pool).await.unwrap(), 1);
+        crawler.crawl_batch().await.unwrap();
+        crawler.drain_index_tasks().await.unwrap();
+
+        let status: String = sqlx::query("SELECT status FROM urls WHERE url = $1")
+            .bind(&mirror)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get("status");
+        assert_eq!(status, "completed");
+        assert!(backend.documents("web_pages").iter().any(|document| document.url == mirror));
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // A sitemap hosted elsewhere only lists pages for the site whose robots.txt declared it
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn sitemap_entries_must_be_on_the_declaring_site() {
+        let (admin, pool, schema) = test_schema("sitemap").await;
+        let server = test_site().await;
+        // The same server stands in for the site (127.0.0.1) and a sitemap host (localhost)
+        let site = server.uri();
+        let elsewhere = site.replace("127.0.0.1", "localhost");
+        let index = format!(
+            "<sitemapindex><sitemap><loc>{0}/pages.xml</loc></sitemap>\
+             <sitemap><loc>{1}/more.xml</loc></sitemap>\
+             <sitemap><loc>http://other.example/pages.xml</loc></sitemap></sitemapindex>",
+            elsewhere, site
+        );
+        let pages = format!(
+            "<urlset><url><loc>{0}/page/1</loc></url><url><loc>{0}/page/2</loc></url>\
+             <url><loc>{1}/page/3</loc></url></urlset>",
+            site, elsewhere
+        );
+        for (name, body) in [("/index.xml", index), ("/pages.xml", pages), ("/more.xml", "<urlset/>".to_string())] {
+            Mock::given(path(name))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/xml"))
+                .mount(&server)
+                .await;
+        }
+
+        let crawler = Crawler::new(pool.clone(), Arc::new(MemoryBackend::default()), test_config(0));
+        crawler
+            .add_sitemap(&format!("{}/index.xml", elsewhere), SitemapSource::Robots, &site, None)
+            .await
+            .unwrap();
+        crawler.sync_sitemaps().await.unwrap();
+        crawler.sync_sitemaps().await.unwrap();
+
+        let counts = sqlx::query("SELECT url, site, url_count FROM sitemaps")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.get::<String, _>("url"), (row.get::<String, _>("site"), row.get::<i32, _>("url_count"))))
+            .collect::<HashMap<_, _>>();
+        // Fetching from localhost also guessed its /sitemap.xml, but nothing came from other.example
+        assert!(!counts.keys().any(|url| url.contains("other.example")), "{:?}", counts);
+        assert_eq!(counts[&format!("{}/index.xml", elsewhere)], (site.clone(), 2));
+        assert_eq!(counts[&format!("{}/pages.xml", elsewhere)], (site.clone(), 2));
+        assert_eq!(counts[&format!("{}/more.xml", site)], (site.clone(), 0));
+
+        let mut urls = sqlx::query("SELECT url FROM urls")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get::<String, _>("url"))
+            .collect::<Vec<_>>();
+        urls.sort();
+        assert_eq!(urls, [format!("{}/page/1", site), format!("{}/page/2", site)]);
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // A host with a long queue gets max_per_host rows of a batch, the rest goes to other hosts
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn claims_are_capped_per_host() {
+        let (admin, pool, schema) = test_schema("claim").await;
+        for (host, pages, priority) in [("busy.example", 50, 10), ("quiet.example", 3, 0), ("other.example", 1, 0)] {
+            for i in 0..pages {
+                sqlx::query("INSERT INTO urls (url, host, depth, priority) VALUES ($1, $2, 0, $3)")
+                    .bind(format!("http://{}/page/{}", host, i))
+                    .bind(host)
+                    .bind(priority)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let config = CrawlerConfig {
+            max_per_host: 2,
+            ..test_config(0)
+        };
+        let crawler = Crawler::new(pool.clone(), Arc::new(MemoryBackend::default()), config);
+
+        let mut claimed: HashMap<String, usize> = HashMap::new();
+        for entry in crawler.fetch_pending_urls(BATCH_SIZE).await.unwrap() {
+            let host = Url::parse(&entry.url).unwrap().host_str().unwrap().to_string();
+            *claimed.entry(host).or_default() += 1;
+        }
+        assert_eq!(claimed.len(), 3, "{:?}", claimed);
+        assert_eq!(claimed["busy.example"], 2);
+        assert_eq!(claimed["quiet.example"], 2);
+        assert_eq!(claimed["other.example"], 1);
+
+        // The next claim picks up where this one left off
+        let next = crawler.fetch_pending_urls(BATCH_SIZE).await.unwrap();
+        assert_eq!(next.len(), 3);
+        assert!(next.iter().any(|entry| entry.url == "http://busy.example/page/2"));
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // URLs a host already has don't use up its max_urls_per_host allowance
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn known_urls_do_not_count_toward_the_host_limit() {
+        let (admin, pool, schema) = test_schema("host_limit").await;
+        let config = CrawlerConfig {
+            scope: CrawlScope {
+                max_urls_per_host: 3,
+                ..CrawlScope::default()
+            },
+            ..test_config(0)
+        };
+        let crawler = Crawler::new(pool.clone(), Arc::new(MemoryBackend::default()), config);
+        let source = Url::parse("http://example.com/").unwrap();
+        let links = |paths: &[&str]| paths.iter().map(|path| format!("http://example.com/{}", path)).collect::<Vec<_>>();
+
+        crawler.add_new_urls(&links(&["a", "b"]), 1, Some(&source)).await.unwrap();
+        // Both known links are skipped over, leaving room for one new one
+        crawler.add_new_urls(&links(&["a", "b", "c", "d"]), 1, Some(&source)).await.unwrap();
+
+        let mut urls = sqlx::query("SELECT url FROM urls")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get::<String, _>("url"))
+            .collect::<Vec<_>>();
+        urls.sort();
+        assert_eq!(urls, links(&["a", "b", "c"]));
+
+        drop_test_schema(admin, pool, schema).await;
+    }
+
+    // An oversized page is given up on right away rather than retried
+    #[tokio::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn oversized_pages_are_marked_too_large() {
+        let (admin, pool, schema) = test_schema("too_large").await;
+        let server = test_site().await;
+        Mock::given(path("/big"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![b'a'; 2 * 1024 * 1024], "text/html"))
+            .mount(&server)
+            .await;
+        sqlx::query("INSERT INTO urls (url, depth) VALUES ($1, 0)")
+            .bind(format!("{}/big", server.uri()))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let crawler = Crawler::new(pool.clone(), Arc::new(MemoryBackend::default()), test_config(0));
+        crawler.crawl_batch().await.unwrap();
+
+        let row = sqlx::query("SELECT status, last_error_kind, next_attempt_at IS NULL AS final FROM urls")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<String, _>("status"), "too_large");
+        assert_eq!(row.get::<String, _>("last_error_kind"), "too_large");
+        assert!(row.get::<bool, _>("final"));
+
+        drop_test_schema(admin, pool, schema).await;
+    }
 }
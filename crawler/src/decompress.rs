@@ -0,0 +1,160 @@
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::io::Read;
+
+use crate::fetcher::FetchError;
+
+// Sent as Accept-Encoding; every coding listed here is decoded by `decode`
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+// Undoes a Content-Encoding such as "gzip" or "gzip, br" (codings are listed in the order
+// they were applied). The decoded body may be at most `limit` bytes, so a small
+// compressed response cannot expand into gigabytes
+pub fn decode(body: Vec<u8>, content_encoding: &str, limit: usize) -> Result<Vec<u8>, FetchError> {
+    decode_with(body, content_encoding, limit, false)
+}
+
+// Like `decode`, but for a body that may have been cut short: returns at most the first
+// `limit` decoded bytes, and whatever could be decoded from a truncated stream
+pub fn decode_prefix(body: Vec<u8>, content_encoding: &str, limit: usize) -> Result<Vec<u8>, FetchError> {
+    decode_with(body, content_encoding, limit, true)
+}
+
+fn decode_with(body: Vec<u8>, content_encoding: &str, limit: usize, prefix: bool) -> Result<Vec<u8>, FetchError> {
+    let codings = content_encoding
+        .split(',')
+        .map(|coding| coding.trim().to_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect::<Vec<_>>();
+
+    let mut body = body;
+    for coding in codings.iter().rev() {
+        body = match coding.as_str() {
+            "gzip" | "x-gzip" => read_limited(GzDecoder::new(body.as_slice()), coding, limit, prefix)?,
+            // Meant to be zlib-wrapped, but some servers send raw deflate
+            "deflate" if is_zlib(&body) => read_limited(ZlibDecoder::new(body.as_slice()), coding, limit, prefix)?,
+            "deflate" => read_limited(DeflateDecoder::new(body.as_slice()), coding, limit, prefix)?,
+            "br" => read_limited(brotli_decompressor::Decompressor::new(body.as_slice(), 4096), coding, limit, prefix)?,
+            "zstd" => {
+                let decoder = zstd::stream::read::Decoder::new(body.as_slice())
+                    .map_err(|e| FetchError::Decode(coding.clone(), e.to_string()))?;
+                read_limited(decoder, coding, limit, prefix)?
+            }
+            other => return Err(FetchError::Decode(other.to_string(), "unsupported coding".to_string())),
+        };
+    }
+
+    Ok(body)
+}
+
+fn read_limited(reader: impl Read, coding: &str, limit: usize, prefix: bool) -> Result<Vec<u8>, FetchError> {
+    let mut decoded = Vec::new();
+    let result = reader.take(limit as u64 + 1).read_to_end(&mut decoded);
+
+    // read_to_end keeps what was decoded before the stream broke off
+    if prefix && (result.is_ok() || !decoded.is_empty()) {
+        decoded.truncate(limit);
+        return Ok(decoded);
+    }
+
+    result.map_err(|e| FetchError::Decode(coding.to_string(), e.to_string()))?;
+    if decoded.len() > limit {
+        return Err(FetchError::TooLarge { limit });
+    }
+    Ok(decoded)
+}
+
+// A zlib stream starts with a two-byte header whose value is a multiple of 31
+fn is_zlib(body: &[u8]) -> bool {
+    matches!(body, [cmf, flg, ..] if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    const TEXT: &[u8] = b"<html><body><p>The same paragraph, over and over again.</p></body></html>";
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn br(body: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+        encoder.write_all(body).unwrap();
+        drop(encoder);
+        encoded
+    }
+
+    #[test]
+    fn single_codings() {
+        assert_eq!(decode(gzip(TEXT), "gzip", 1024).unwrap(), TEXT);
+        assert_eq!(decode(gzip(TEXT), "X-GZIP", 1024).unwrap(), TEXT);
+        assert_eq!(decode(br(TEXT), "br", 1024).unwrap(), TEXT);
+        assert_eq!(decode(zstd::encode_all(TEXT, 3).unwrap(), "zstd", 1024).unwrap(), TEXT);
+        assert_eq!(decode(TEXT.to_vec(), "identity", 1024).unwrap(), TEXT);
+        assert_eq!(decode(TEXT.to_vec(), "", 1024).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn zlib_and_raw_deflate() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(TEXT).unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert!(is_zlib(&zlib));
+        assert_eq!(decode(zlib, "deflate", 1024).unwrap(), TEXT);
+
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(TEXT).unwrap();
+        let raw = raw.finish().unwrap();
+        assert!(!is_zlib(&raw));
+        assert_eq!(decode(raw, "deflate", 1024).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn codings_are_undone_in_reverse_order() {
+        // "gzip, br": gzipped first, then brotli on top
+        let body = br(&gzip(TEXT));
+        assert_eq!(decode(body.clone(), "gzip, br", 1024).unwrap(), TEXT);
+        assert!(decode(body, "br, gzip", 1024).is_err());
+    }
+
+    #[test]
+    fn decoded_size_is_capped() {
+        let bomb = gzip(&vec![b'a'; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+        assert!(matches!(decode(bomb, "gzip", 64 * 1024), Err(FetchError::TooLarge { limit: 65536 })));
+
+        // A multi-coding body is capped too
+        let body = br(&gzip(TEXT));
+        assert!(matches!(decode(body, "gzip, br", TEXT.len() - 1), Err(FetchError::TooLarge { .. })));
+        assert_eq!(decode(gzip(TEXT), "gzip", TEXT.len()).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn unsupported_and_corrupt_bodies_are_errors() {
+        assert!(matches!(decode(TEXT.to_vec(), "compress", 1024), Err(FetchError::Decode(coding, _)) if coding == "compress"));
+        assert!(matches!(decode(TEXT.to_vec(), "gzip", 1024), Err(FetchError::Decode(..))));
+    }
+
+    #[test]
+    fn prefixes_are_decoded_from_truncated_streams() {
+        let body = vec![b'a'; 64 * 1024];
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(&body).unwrap();
+        let mut gzipped = encoder.finish().unwrap();
+        gzipped.truncate(gzipped.len() / 2);
+
+        let prefix = decode_prefix(gzipped.clone(), "gzip", 1024 * 1024).unwrap();
+        assert!(!prefix.is_empty() && prefix.len() < body.len(), "{} bytes", prefix.len());
+        assert!(decode(gzipped, "gzip", 1024 * 1024).is_err());
+
+        assert_eq!(decode_prefix(gzip(&body), "gzip", 100).unwrap(), &body[..100]);
+        assert!(decode_prefix(TEXT.to_vec(), "gzip", 1024).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use reqwest::StatusCode;
+use std::error::Error;
+
+use crate::fetcher::FetchError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CrawlError {
+    #[error(transparent)]
+    Fetch(#[from] FetchError),
+    #[error("Parse error: {0}")]
+    Parse(#[from] url::ParseError),
+    #[error("Meilisearch error {status}: {body}")]
+    Index { status: StatusCode, body: String },
+    #[error("Meilisearch request failed: {0}")]
+    IndexRequest(#[source] reqwest::Error),
+    #[error("Meilisearch task {uid} failed: {message}")]
+    IndexTask { uid: u64, message: String },
+    #[error("Timed out waiting for Meilisearch task {uid}")]
+    IndexTimeout { uid: u64 },
+    #[cfg(feature = "tantivy")]
+    #[error("Tantivy error: {0}")]
+    Tantivy(#[from] tantivy::TantivyError),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// Stored in urls.last_error_kind, so variant names are part of the schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidUrl,
+    RobotsBlocked,
+    RobotsUnavailable,
+    Timeout,
+    Dns,
+    Connection,
+    ServerError,
+    RateLimited,
+    ClientError,
+    NotHtml,
+    Redirect,
+    TooLarge,
+    Decode,
+    Request,
+    Parse,
+    Index,
+    IndexTask,
+    Database,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidUrl => "invalid_url",
+            Self::RobotsBlocked => "robots_blocked",
+            Self::RobotsUnavailable => "robots_unavailable",
+            Self::Timeout => "timeout",
+            Self::Dns => "dns",
+            Self::Connection => "connection",
+            Self::ServerError => "http_5xx",
+            Self::RateLimited => "http_429",
+            Self::ClientError => "http_4xx",
+            Self::NotHtml => "not_html",
+            Self::Redirect => "redirect",
+            Self::TooLarge => "too_large",
+            Self::Decode => "decode",
+            Self::Request => "request",
+            Self::Parse => "parse",
+            Self::Index => "index",
+            Self::IndexTask => "index_task",
+            Self::Database => "database",
+        }
+    }
+
+    // Asking again won't change a bad URL, a 4xx, a redirect loop, a non-HTML or oversized
+    // response or a rejected document
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            Self::InvalidUrl
+                | Self::RobotsBlocked
+                | Self::ClientError
+                | Self::NotHtml
+                | Self::Redirect
+                | Self::TooLarge
+                | Self::Parse
+                | Self::IndexTask
+        )
+    }
+
+    // Conditions of the whole host rather than the URL, which wait without using up the
+    // URL's retries
+    pub fn is_host_level(&self) -> bool {
+        matches!(self, Self::RobotsUnavailable)
+    }
+}
+
+impl CrawlError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Fetch(FetchError::InvalidUrl(_)) => ErrorKind::InvalidUrl,
+            Self::Fetch(FetchError::RobotsBlocked) => ErrorKind::RobotsBlocked,
+            Self::Fetch(FetchError::RobotsUnavailable { .. }) => ErrorKind::RobotsUnavailable,
+            Self::Fetch(FetchError::Status { status, .. }) => match *status {
+                StatusCode::TOO_MANY_REQUESTS => ErrorKind::RateLimited,
+                StatusCode::REQUEST_TIMEOUT => ErrorKind::Timeout,
+                status if status.is_server_error() => ErrorKind::ServerError,
+                _ => ErrorKind::ClientError,
+            },
+            Self::Fetch(FetchError::NotHtml) => ErrorKind::NotHtml,
+            Self::Fetch(FetchError::Redirect(_)) => ErrorKind::Redirect,
+            Self::Fetch(FetchError::TooLarge { .. }) => ErrorKind::TooLarge,
+            Self::Fetch(FetchError::Decode(..)) => ErrorKind::Decode,
+            Self::Fetch(FetchError::Request(e)) if e.is_timeout() => ErrorKind::Timeout,
+            Self::Fetch(FetchError::Request(e)) if e.is_connect() && is_dns_error(e) => ErrorKind::Dns,
+            Self::Fetch(FetchError::Request(e)) if e.is_connect() => ErrorKind::Connection,
+            Self::Fetch(FetchError::Request(_)) => ErrorKind::Request,
+            Self::Parse(_) => ErrorKind::Parse,
+            Self::Index { .. } | Self::IndexRequest(_) | Self::IndexTimeout { .. } => ErrorKind::Index,
+            Self::IndexTask { .. } => ErrorKind::IndexTask,
+            #[cfg(feature = "tantivy")]
+            Self::Tantivy(_) => ErrorKind::Index,
+            Self::Database(_) => ErrorKind::Database,
+        }
+    }
+}
+
+// hyper only reports DNS failures in the error message
+fn is_dns_error(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(e) = source {
+        if e.to_string().contains("dns error") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
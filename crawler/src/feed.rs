@@ -0,0 +1,153 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+// Most items read from one feed, which usually lists a few dozen
+const MAX_ITEMS: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub link: String,
+    // <updated>, <pubDate> or <dc:date>, whichever the feed has
+    pub updated: Option<DateTime<Utc>>,
+}
+
+// Reads the item links of an RSS 2.0, RSS 1.0 (RDF) or Atom feed
+pub fn parse(body: &[u8]) -> Result<Vec<FeedItem>> {
+    let text = String::from_utf8_lossy(body);
+    let document = roxmltree::Document::parse(&text)?;
+    let root = document.root_element();
+
+    // Elements are matched by local name, which covers the namespaced RDF and Atom variants
+    let items = match root.tag_name().name() {
+        "rss" | "RDF" => "item",
+        "feed" => "entry",
+        other => anyhow::bail!("Not a feed: root element <{}>", other),
+    };
+
+    Ok(root
+        .descendants()
+        .filter(|node| node.tag_name().name() == items)
+        .filter_map(|node| {
+            let link = match items {
+                // Atom links are attributes; the page itself is rel="alternate" or has no rel
+                "entry" => node
+                    .children()
+                    .filter(|child| child.tag_name().name() == "link")
+                    .find(|child| child.attribute("rel").is_none_or(|rel| rel == "alternate"))
+                    .and_then(|child| child.attribute("href"))
+                    .map(str::trim),
+                // RSS items without <link> often carry the URL as a permalink <guid>
+                _ => child_text(node, "link").or_else(|| {
+                    node.children()
+                        .find(|child| child.tag_name().name() == "guid")
+                        .filter(|guid| guid.attribute("isPermaLink") != Some("false"))
+                        .and_then(|guid| guid.text())
+                        .map(str::trim)
+                }),
+            }?;
+
+            let updated = ["updated", "published", "pubDate", "date"]
+                .iter()
+                .find_map(|name| child_text(node, name))
+                .and_then(parse_date);
+
+            Some(FeedItem {
+                link: link.to_string(),
+                updated,
+            })
+        })
+        .take(MAX_ITEMS)
+        .collect())
+}
+
+// RSS uses RFC 2822 dates, Atom and Dublin Core RFC 3339
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rss_links_and_permalink_guids() {
+        let items = parse(
+            br#"<rss version="2.0"><channel>
+                <link>http://example.com/</link>
+                <item><link> http://example.com/one </link><pubDate>Tue, 05 Mar 2024 10:00:00 +0100</pubDate></item>
+                <item><guid>http://example.com/two</guid></item>
+                <item><guid isPermaLink="true">http://example.com/three</guid></item>
+                <item><guid isPermaLink="false">tag:example.com,2024:4</guid></item>
+                <item><link>http://example.com/five</link><guid isPermaLink="false">5</guid></item>
+            </channel></rss>"#,
+        )
+        .unwrap();
+
+        // The channel's own <link> is not an item, and a guid that is not a permalink is no URL
+        let links = items.iter().map(|item| item.link.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            ["http://example.com/one", "http://example.com/two", "http://example.com/three", "http://example.com/five"]
+        );
+        assert_eq!(items[0].updated.unwrap().to_rfc3339(), "2024-03-05T09:00:00+00:00");
+        assert!(items[1].updated.is_none());
+    }
+
+    #[test]
+    fn rdf_items_with_dublin_core_dates() {
+        let items = parse(
+            br#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+                         xmlns="http://purl.org/rss/1.0/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+                <channel><link>http://example.com/</link></channel>
+                <item><link>http://example.com/rdf</link><dc:date>2024-03-05T10:00:00Z</dc:date></item>
+            </rdf:RDF>"#,
+        )
+        .unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].link, "http://example.com/rdf");
+        assert_eq!(items[0].updated.unwrap().to_rfc3339(), "2024-03-05T10:00:00+00:00");
+    }
+
+    #[test]
+    fn atom_alternate_links() {
+        let items = parse(
+            br#"<feed xmlns="http://www.w3.org/2005/Atom">
+                <link rel="self" href="http://example.com/feed.xml"/>
+                <entry>
+                    <link rel="edit" href="http://example.com/edit/1"/>
+                    <link rel="alternate" href="http://example.com/1"/>
+                    <updated>2024-03-05T10:00:00+02:00</updated>
+                </entry>
+                <entry><link href="http://example.com/2"/><published>2024-03-04T00:00:00Z</published></entry>
+                <entry><link rel="enclosure" href="http://example.com/3.mp3"/></entry>
+            </feed>"#,
+        )
+        .unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].link, "http://example.com/1");
+        assert_eq!(items[0].updated.unwrap().to_rfc3339(), "2024-03-05T08:00:00+00:00");
+        assert_eq!(items[1].link, "http://example.com/2");
+        assert!(items[1].updated.is_some());
+    }
+
+    #[test]
+    fn other_documents_are_rejected() {
+        assert!(parse(b"<urlset><url><loc>http://example.com/</loc></url></urlset>").is_err());
+        assert!(parse(b"<html><body>Moved</body></html>").is_err());
+        assert!(parse(b"").is_err());
+        assert!(parse(b"<rss><channel/></rss>").unwrap().is_empty());
+    }
+}
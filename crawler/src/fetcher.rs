@@ -1,52 +1,576 @@
-use anyhow::Result;
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, LOCATION, RETRY_AFTER,
+};
+use reqwest::{Client, StatusCode};
 use std::time::Duration;
 use tracing::{debug, warn};
+use url::Url;
+
+use crate::charset;
+use crate::decompress;
+use crate::parser::RobotsDirectives;
+use crate::politeness::{HostPermit, HostScheduler};
+use crate::robots::{product_token, RobotsCache, RobotsCheck};
+use crate::sitemap::SitemapSource;
+
+const USER_AGENT: &str = "SearchBot/1.0 (+https://github.com/yourusername/search-engine)";
+
+// Media types parsed as pages; index routes may only name these
+pub const HTML_MEDIA_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+
+// Redirects followed per fetch, each hop checked against robots.txt like the first request
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("Blocked by robots.txt")]
+    RobotsBlocked,
+    #[error("robots.txt unavailable")]
+    RobotsUnavailable { retry_after: Duration },
+    #[error("HTTP error: {status}")]
+    Status {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    #[error("Not HTML content")]
+    NotHtml,
+    #[error("Redirect not followed: {0}")]
+    Redirect(String),
+    #[error("Body larger than {limit} bytes")]
+    TooLarge { limit: usize },
+    #[error("Failed to decode {0} body: {1}")]
+    Decode(String, String),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+// Caps on how much of a response is read into memory
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    // Bytes read from the network per response
+    pub max_body_bytes: usize,
+    // Bytes a compressed body may expand to
+    pub max_decompressed_bytes: usize,
+}
+
+// Size of one response as sent and after undoing its Content-Encoding
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Transfer {
+    pub wire_bytes: usize,
+    pub decoded_bytes: usize,
+}
+
+// Cache validators from the last fetch, sent back to make the request conditional
+#[derive(Debug, Default, Clone)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+pub struct FetchedPage {
+    pub html: String,
+    pub content_type: String,
+    pub validators: Validators,
+    // From X-Robots-Tag headers addressed to all crawlers or to us
+    pub robots: RobotsDirectives,
+    pub transfer: Transfer,
+}
+
+pub enum FetchOutcome {
+    Modified(FetchedPage),
+    // The server answered 304 to our validators
+    NotModified,
+}
 
 pub struct Fetcher {
     client: Client,
+    robots: RobotsCache,
+    scheduler: HostScheduler,
+    limits: BodyLimits,
 }
 
 impl Fetcher {
-    pub fn new() -> Self {
+    pub fn new(delay_ms: u64, max_per_host: usize, limits: BodyLimits) -> Self {
+        // reqwest is built without its decompression features, so compressed bodies reach
+        // read_body as sent and both sizes can be counted
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(decompress::ACCEPT_ENCODING));
+
         let client = Client::builder()
-            .user_agent("SearchBot/1.0 (+https://github.com/yourusername/search-engine)")
+            .user_agent(USER_AGENT)
+            .default_headers(headers.clone())
             .timeout(Duration::from_secs(10))
-            .redirect(reqwest::redirect::Policy::limited(5))
+            .redirect(reqwest::redirect::Policy::none())
             .build()
             .expect("Failed to create HTTP client");
 
-        Self { client }
+        // robots.txt itself may redirect, usually to https or another host name
+        let robots_client = Client::builder()
+            .user_agent(USER_AGENT)
+            .default_headers(headers)
+            .timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+            .build()
+            .expect("Failed to create HTTP client");
+        let robots = RobotsCache::new(robots_client, USER_AGENT);
+
+        let scheduler = HostScheduler::new(Duration::from_millis(delay_ms), max_per_host);
+
+        Self { client, robots, scheduler, limits }
     }
 
-    pub async fn fetch(&self, url: &str) -> Result<String> {
+    pub async fn fetch(&self, url: &str, validators: &Validators) -> Result<FetchOutcome, FetchError> {
+        let parsed_url = Url::parse(url)?;
+
         debug!("Fetching URL: {}", url);
 
-        let response = self.client
-            .get(url)
-            .send()
-            .await?;
+        let (response, _permit) = self.get(&parsed_url, validators).await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            debug!("Not modified: {}", url);
+            return Ok(FetchOutcome::NotModified);
+        }
 
         if !response.status().is_success() {
             warn!("Non-success status for {}: {}", url, response.status());
-            anyhow::bail!("HTTP error: {}", response.status());
+            return Err(FetchError::Status {
+                status: response.status(),
+                retry_after: parse_retry_after(response.headers()),
+            });
         }
 
         let content_type = response
             .headers()
             .get("content-type")
             .and_then(|v| v.to_str().ok())
-            .unwrap_or("");
+            .unwrap_or("")
+            .to_string();
 
         // Only process HTML content
-        if !content_type.contains("text/html") {
+        if !HTML_MEDIA_TYPES.contains(&media_type(&content_type).as_str()) {
             debug!("Skipping non-HTML content: {}", content_type);
-            anyhow::bail!("Not HTML content");
+            return Err(FetchError::NotHtml);
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+
+        let robots = parse_x_robots_tag(response.headers(), product_token(USER_AGENT));
+
+        // Decoded by us rather than reqwest, which only honors the Content-Type charset
+        let (body, transfer) = read_body(response, self.limits).await?;
+        let (html, encoding) = charset::decode(&body, &content_type, &parsed_url);
+        debug!(
+            "Fetched {} bytes ({} on the wire) from {} as {}",
+            transfer.decoded_bytes, transfer.wire_bytes, url, encoding.name()
+        );
+
+        Ok(FetchOutcome::Modified(FetchedPage { html, content_type, validators, robots, transfer }))
+    }
+
+    // The body of any content type, for sitemaps and feeds
+    pub async fn fetch_bytes(&self, url: &str) -> Result<(Vec<u8>, Transfer), FetchError> {
+        debug!("Fetching bytes: {}", url);
+
+        let (response, _permit) = self.get(&Url::parse(url)?, &Validators::default()).await?;
+        if !response.status().is_success() {
+            return Err(FetchError::Status {
+                status: response.status(),
+                retry_after: parse_retry_after(response.headers()),
+            });
+        }
+
+        read_body(response, self.limits).await
+    }
+
+    pub fn limits(&self) -> BodyLimits {
+        self.limits
+    }
+
+    pub fn take_sitemaps(&self) -> Vec<(String, SitemapSource, String)> {
+        self.robots.take_sitemaps()
+    }
+
+    // Sends the request and follows redirects, admitting every hop like the first so a
+    // redirect cannot lead to a disallowed path or skip another host's politeness delay.
+    // The permit of the last hop is held until the body has been read
+    async fn get(&self, url: &Url, validators: &Validators) -> Result<(reqwest::Response, HostPermit), FetchError> {
+        let mut url = url.clone();
+
+        for hop in 0..=MAX_REDIRECTS {
+            let permit = self.admit(&url).await?;
+
+            // Validators belong to the URL we fetched before, not to where it redirects
+            let mut request = self.client.get(url.clone());
+            if hop == 0 {
+                if let Some(etag) = &validators.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+            let response = request.send().await?;
+
+            let redirect = matches!(
+                response.status(),
+                StatusCode::MOVED_PERMANENTLY
+                    | StatusCode::FOUND
+                    | StatusCode::SEE_OTHER
+                    | StatusCode::TEMPORARY_REDIRECT
+                    | StatusCode::PERMANENT_REDIRECT
+            );
+            let location = response.headers().get(LOCATION).and_then(|v| v.to_str().ok());
+            let Some(location) = location.filter(|_| redirect) else {
+                return Ok((response, permit));
+            };
+
+            let next = url.join(location)?;
+            if !matches!(next.scheme(), "http" | "https") {
+                return Err(FetchError::Redirect(format!("to {}", next)));
+            }
+            debug!("Following redirect from {} to {}", url, next);
+            url = next;
+        }
+
+        Err(FetchError::Redirect(format!("more than {} redirects", MAX_REDIRECTS)))
+    }
+
+    // Checks robots.txt before touching the URL itself, then waits for our turn on its host
+    async fn admit(&self, url: &Url) -> Result<HostPermit, FetchError> {
+        let crawl_delay = match self.robots.check(url).await {
+            RobotsCheck::Allowed { crawl_delay } => crawl_delay,
+            RobotsCheck::Disallowed => {
+                debug!("Disallowed by robots.txt: {}", url);
+                return Err(FetchError::RobotsBlocked);
+            }
+            RobotsCheck::Unavailable { retry_after } => {
+                debug!("robots.txt unavailable for {}", url);
+                return Err(FetchError::RobotsUnavailable { retry_after });
+            }
+        };
+
+        let host = url.host_str().unwrap_or_default();
+        Ok(self.scheduler.acquire(host, crawl_delay).await)
+    }
+}
+
+// Streams the body, giving up as soon as it passes max_body_bytes so an endless or huge
+// response never sits in memory, then undoes its Content-Encoding
+pub async fn read_body(mut response: reqwest::Response, limits: BodyLimits) -> Result<(Vec<u8>, Transfer), FetchError> {
+    let content_encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let limit = limits.max_body_bytes;
+    if response.content_length().is_some_and(|length| length > limit as u64) {
+        return Err(FetchError::TooLarge { limit });
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(FetchError::TooLarge { limit });
         }
+        body.extend_from_slice(&chunk);
+    }
+
+    let wire_bytes = body.len();
+    let body = decompress::decode(body, &content_encoding, limits.max_decompressed_bytes)?;
+    let transfer = Transfer {
+        wire_bytes,
+        decoded_bytes: body.len(),
+    };
+
+    Ok((body, transfer))
+}
+
+// Reads the first `limit` bytes of a body that is still usable when cut short, decoded
+// from as much of the compressed stream as arrived
+pub async fn read_body_prefix(mut response: reqwest::Response, limit: usize) -> Result<Vec<u8>, FetchError> {
+    let content_encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk[..chunk.len().min(limit - body.len())]);
+        if body.len() == limit {
+            break;
+        }
+    }
+
+    decompress::decode_prefix(body, &content_encoding, limit)
+}
+
+// "text/html; charset=utf-8" -> "text/html"
+pub fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+// Each X-Robots-Tag value is either a directive list for every crawler or one prefixed
+// with a crawler name ("SearchBot: noindex"), which only counts when it names us
+fn parse_x_robots_tag(headers: &HeaderMap, product: &str) -> RobotsDirectives {
+    headers
+        .get_all("x-robots-tag")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| match value.split_once(':') {
+            // Directives that carry a value, like "unavailable_after: <date>", contain a colon too
+            Some((name, directives)) if !name.contains(',') && !is_valued_directive(name) => {
+                name.trim().eq_ignore_ascii_case(product).then_some(directives)
+            }
+            _ => Some(value),
+        })
+        .map(RobotsDirectives::parse)
+        .fold(RobotsDirectives::default(), RobotsDirectives::merge)
+}
+
+fn is_valued_directive(name: &str) -> bool {
+    matches!(
+        name.trim().to_lowercase().as_str(),
+        "unavailable_after" | "max-snippet" | "max-image-preview" | "max-video-preview"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use wiremock::matchers::{header, header_exists, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn fetcher() -> Fetcher {
+        fetcher_with_limit(1024 * 1024)
+    }
+
+    fn fetcher_with_limit(max_body_bytes: usize) -> Fetcher {
+        Fetcher::new(
+            0,
+            4,
+            BodyLimits {
+                max_body_bytes,
+                max_decompressed_bytes: 1024 * 1024,
+            },
+        )
+    }
+
+    // Answers every page request with the raw `response`, for bodies wiremock cannot send:
+    // chunked without a length, or shorter than the length they declare
+    async fn raw_server(response: Vec<u8>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = vec![0; 4096];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let reply = if request[..read].starts_with(b"GET /robots.txt") {
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec()
+                } else {
+                    response.clone()
+                };
+                let _ = socket.write_all(&reply).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn redirects_are_checked_against_robots_txt() {
+        let server = MockServer::start().await;
+        Mock::given(path("/robots.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("User-agent: *\nDisallow: /private\n"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/old"))
+            .respond_with(ResponseTemplate::new(301).insert_header("location", "/new"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/leak"))
+            .respond_with(ResponseTemplate::new(302).insert_header("location", "/private/page"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/new"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<p>moved</p>", "text/html"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/private/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<p>secret</p>", "text/html"))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let fetcher = fetcher();
+        let validators = Validators::default();
+
+        let page = fetcher.fetch(&format!("{}/old", server.uri()), &validators).await.unwrap();
+        assert!(matches!(page, FetchOutcome::Modified(page) if page.html.contains("moved")));
+
+        let blocked = fetcher.fetch(&format!("{}/leak", server.uri()), &validators).await;
+        assert!(matches!(blocked, Err(FetchError::RobotsBlocked)));
+    }
+
+    #[tokio::test]
+    async fn redirect_loops_give_up() {
+        let server = MockServer::start().await;
+        Mock::given(path("/robots.txt"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(path("/loop"))
+            .respond_with(ResponseTemplate::new(302).insert_header("location", "/loop"))
+            .mount(&server)
+            .await;
+
+        let result = fetcher().fetch(&format!("{}/loop", server.uri()), &Validators::default()).await;
+        assert!(matches!(result, Err(FetchError::Redirect(_))));
+    }
+
+    #[tokio::test]
+    async fn validators_go_with_the_first_hop_only() {
+        let server = MockServer::start().await;
+        Mock::given(path("/robots.txt"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(path("/cached"))
+            .and(header("if-none-match", "\"v1\""))
+            // header() would split the date at its comma
+            .and(header_exists("if-modified-since"))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(path("/moved"))
+            .respond_with(ResponseTemplate::new(301).insert_header("location", "/new"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/new"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<p>new</p>", "text/html"))
+            .mount(&server)
+            .await;
+
+        let fetcher = fetcher();
+        let validators = Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some("Tue, 05 Mar 2024 10:00:00 GMT".to_string()),
+        };
+
+        let cached = fetcher.fetch(&format!("{}/cached", server.uri()), &validators).await.unwrap();
+        assert!(matches!(cached, FetchOutcome::NotModified));
+
+        // They describe the copy of /moved we have, not whatever it redirects to
+        let moved = fetcher.fetch(&format!("{}/moved", server.uri()), &validators).await.unwrap();
+        assert!(matches!(moved, FetchOutcome::Modified(page) if page.html.contains("new")));
+
+        let requests = server.received_requests().await.unwrap();
+        let sent = |path: &str| {
+            let request = requests.iter().find(|request| request.url.path() == path).unwrap();
+            let has = |name: &str| request.headers.contains_key(&name.into());
+            (has("if-none-match"), has("if-modified-since"))
+        };
+        assert_eq!(sent("/moved"), (true, true));
+        assert_eq!(sent("/new"), (false, false));
+    }
+
+    #[tokio::test]
+    async fn declared_length_over_the_limit_is_refused_unread() {
+        // Only a few bytes of the declared megabyte ever arrive
+        let server = raw_server(b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 1048576\r\n\r\n<p>".to_vec()).await;
+        let result = fetcher_with_limit(1024).fetch(&format!("{}/page", server), &Validators::default()).await;
+        assert!(matches!(result, Err(FetchError::TooLarge { limit: 1024 })), "{:?}", result.err());
+
+        let server = MockServer::start().await;
+        Mock::given(path("/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![b'a'; 2048], "text/html"))
+            .mount(&server)
+            .await;
+        let result = fetcher_with_limit(1024).fetch_bytes(&format!("{}/page", server.uri())).await;
+        assert!(matches!(result, Err(FetchError::TooLarge { limit: 1024 })));
+    }
+
+    #[tokio::test]
+    async fn streamed_body_over_the_limit_is_cut_off() {
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for _ in 0..4 {
+            response.extend_from_slice(format!("200\r\n{}\r\n", "a".repeat(0x200)).as_bytes());
+        }
+        response.extend_from_slice(b"0\r\n\r\n");
+        let server = raw_server(response).await;
+
+        let result = fetcher_with_limit(1024).fetch(&format!("{}/page", server), &Validators::default()).await;
+        assert!(matches!(result, Err(FetchError::TooLarge { limit: 1024 })), "{:?}", result.err());
+
+        // The same body fits a larger limit
+        let result = fetcher_with_limit(4096).fetch(&format!("{}/page", server), &Validators::default()).await;
+        assert!(matches!(result, Ok(FetchOutcome::Modified(page)) if page.html.len() == 2048));
+    }
+
+    fn x_robots_tag(values: &[&str]) -> RobotsDirectives {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-robots-tag", value.parse().unwrap());
+        }
+        parse_x_robots_tag(&headers, "SearchBot")
+    }
+
+    #[test]
+    fn x_robots_tag_for_other_crawlers_is_ignored() {
+        assert!(x_robots_tag(&["SearchBot: noindex"]).noindex);
+        assert!(x_robots_tag(&["searchbot: none"]).nofollow);
+        assert_eq!(x_robots_tag(&["OtherBot: noindex, nofollow"]), RobotsDirectives::default());
+    }
+
+    #[test]
+    fn valued_directives_are_not_user_agents() {
+        assert!(x_robots_tag(&["max-snippet: 10, nofollow"]).nofollow);
+        assert!(x_robots_tag(&["unavailable_after: 25 Jun 2030 15:00:00 PST, noindex"]).noindex);
+        // A colon after the first comma belongs to a directive, not a user agent
+        assert!(x_robots_tag(&["noindex, max-image-preview: large"]).noindex);
+    }
 
-        let html = response.text().await?;
-        debug!("Fetched {} bytes from {}", html.len(), url);
+    #[test]
+    fn x_robots_tag_headers_are_merged() {
+        let directives = x_robots_tag(&["noindex", "SearchBot: nofollow", "OtherBot: none"]);
+        assert_eq!(directives, RobotsDirectives { noindex: true, nofollow: true });
 
-        Ok(html)
+        let directives = x_robots_tag(&["OtherBot: noindex", "nofollow"]);
+        assert_eq!(directives, RobotsDirectives { noindex: false, nofollow: true });
     }
 }
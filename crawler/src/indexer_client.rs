@@ -1,114 +1,261 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use sha2::{Sha256, Digest};
-use chrono::Utc;
-use tracing::{debug, error};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Document {
-    pub id: String,
-    pub url: String,
-    pub name: String,
-    pub title: String,
-    pub description: String,
-    pub icon: String,
-    pub content: String,
-    pub timestamp: String,
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+use crate::error::CrawlError;
+use crate::search_backend::{Document, IndexOutcome, SearchBackend};
+
+// Used when MEILISEARCH_SETTINGS_PATH is not set
+const DEFAULT_SETTINGS: &str = include_str!("../index_config.json");
+
+// Settings that name document attributes, checked against the Document fields
+const ATTRIBUTE_SETTINGS: [&str; 4] = [
+    "searchableAttributes",
+    "displayedAttributes",
+    "filterableAttributes",
+    "sortableAttributes",
+];
+
+// How long startup waits for Meilisearch to apply a settings update
+const SETTINGS_TASK_TIMEOUT: Duration = Duration::from_secs(60);
+
+// How long a page's removal waits for Meilisearch to delete its document
+const DELETE_TASK_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Documents are sent to Meilisearch in batches, whichever limit is hit first;
+// partial batches go out when the crawler flushes
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_documents: usize,
+    pub max_bytes: usize,
+}
+
+#[derive(Default)]
+struct PendingBatch {
+    documents: Vec<Document>,
+    url_ids: Vec<i32>,
+    bytes: usize,
+}
+
+// A batch Meilisearch accepted but has not processed yet
+struct EnqueuedTask {
+    uid: u64,
+    url_ids: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskEnqueued {
+    task_uid: u64,
+}
+
+#[derive(Deserialize)]
+struct TaskList {
+    results: Vec<TaskStatus>,
+}
+
+#[derive(Deserialize)]
+struct TaskStatus {
+    uid: u64,
+    status: String,
+    error: Option<TaskError>,
+}
+
+#[derive(Deserialize)]
+struct TaskError {
+    message: String,
 }
 
 pub struct IndexerClient {
     client: Client,
     base_url: String,
     api_key: String,
+    settings: Value,
+    batch: BatchConfig,
+    // Keyed by index uid
+    pending: Mutex<HashMap<String, PendingBatch>>,
+    enqueued: Mutex<Vec<EnqueuedTask>>,
+    settled: Mutex<Vec<IndexOutcome>>,
 }
 
 impl IndexerClient {
-    pub fn new(base_url: String, api_key: String) -> Self {
+    pub fn new(base_url: String, api_key: String, settings: Value, batch: BatchConfig) -> Self {
         let client = Client::new();
         Self {
             client,
             base_url,
             api_key,
+            settings,
+            batch,
+            pending: Mutex::new(HashMap::new()),
+            enqueued: Mutex::new(Vec::new()),
+            settled: Mutex::new(Vec::new()),
         }
     }
 
-    pub async fn index_document(&self, url: &str, name: &str, title: &str, description: &str, icon: &str, content: &str) -> Result<()> {
-        // Generate unique ID from URL
-        let id = self.generate_id(url);
+    async fn queue_document(&self, index: &str, url_id: i32, document: Document) {
+        // Queue the document, sending the batch once it is full
+        let bytes = serde_json::to_vec(&document).map(|json| json.len()).unwrap_or_default();
+        let full_batch = {
+            let mut batches = self.pending.lock().unwrap();
+            let pending = batches.entry(index.to_string()).or_default();
+            pending.documents.push(document);
+            pending.url_ids.push(url_id);
+            pending.bytes += bytes;
 
-        // Fallback for description if empty
-        let mut final_description = if description.is_empty() {
-            content
-                .chars()
-                .take(400) // Increase take for better sentence completion
-                .collect::<String>()
-                .trim()
-                .to_string()
-        } else {
-            description.to_string()
+            if pending.documents.len() >= self.batch.max_documents || pending.bytes >= self.batch.max_bytes {
+                Some(mem::take(pending))
+            } else {
+                None
+            }
         };
 
-        // Final polishing of the description: remove redundant whitespace and artifacts
-        final_description = final_description
-            .replace("  ", " ")
-            .replace(" .", ".")
-            .replace(" ,", ",")
-            .trim()
-            .to_string();
-
-        let document = Document {
-            id,
-            url: url.to_string(),
-            name: name.to_string(),
-            title: title.to_string(),
-            description: final_description,
-            icon: icon.to_string(),
-            content: content.to_string(),
-            timestamp: Utc::now().to_rfc3339(),
-        };
+        if let Some(batch) = full_batch {
+            self.send_batch(index, batch).await;
+        }
+    }
 
-        self.send_to_meilisearch(&document).await?;
+    async fn send_batch(&self, index: &str, batch: PendingBatch) {
+        match self.send_to_meilisearch(index, &batch.documents).await {
+            Ok(uid) => self.enqueued.lock().unwrap().push(EnqueuedTask {
+                uid,
+                url_ids: batch.url_ids,
+            }),
+            Err(e) => {
+                error!("Failed to send batch of {} documents ({} bytes) to {}: {}", batch.documents.len(), batch.bytes, index, e);
+                self.settled.lock().unwrap().push(IndexOutcome {
+                    url_ids: batch.url_ids,
+                    result: Err(e),
+                });
+            }
+        }
+    }
 
-        Ok(())
+    // Collects batches that finished since the last call, asking Meilisearch about all
+    // outstanding tasks in one request
+    async fn poll_tasks(&self) -> Result<Vec<IndexOutcome>, CrawlError> {
+        let uids = self
+            .enqueued
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|task| task.uid.to_string())
+            .collect::<Vec<_>>();
+
+        let mut outcomes = mem::take(&mut *self.settled.lock().unwrap());
+        if uids.is_empty() {
+            return Ok(outcomes);
+        }
+
+        let url = format!("{}/tasks", self.base_url);
+        let response = self.client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .query(&[("uids", uids.join(",")), ("limit", uids.len().to_string())])
+            .send()
+            .await
+            .map_err(CrawlError::IndexRequest)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(CrawlError::Index { status, body });
+        }
+
+        let tasks: TaskList = response.json().await.map_err(CrawlError::IndexRequest)?;
+
+        let mut enqueued = self.enqueued.lock().unwrap();
+        for task in tasks.results {
+            let result = match task.status.as_str() {
+                "succeeded" => Ok(()),
+                "failed" | "canceled" => Err(CrawlError::IndexTask {
+                    uid: task.uid,
+                    message: task
+                        .error
+                        .map(|error| error.message)
+                        .unwrap_or_else(|| task.status.clone()),
+                }),
+                // enqueued or processing
+                _ => continue,
+            };
+
+            if let Some(position) = enqueued.iter().position(|pending| pending.uid == task.uid) {
+                let finished = enqueued.remove(position);
+                outcomes.push(IndexOutcome {
+                    url_ids: finished.url_ids,
+                    result,
+                });
+            }
+        }
+
+        Ok(outcomes)
     }
 
-    async fn send_to_meilisearch(&self, document: &Document) -> Result<()> {
-        let url = format!("{}/indexes/web_pages/documents", self.base_url);
+    async fn send_to_meilisearch(&self, index: &str, documents: &[Document]) -> Result<u64, CrawlError> {
+        let url = format!("{}/indexes/{}/documents", self.base_url, index);
 
-        debug!("Indexing document: {}", document.url);
+        debug!("Indexing {} documents", documents.len());
 
         let response = self.client
             .post(&url)
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&vec![document])
+            .json(documents)
             .send()
-            .await?;
+            .await
+            .map_err(CrawlError::IndexRequest)?;
 
         if !response.status().is_success() {
             let status = response.status();
-            let body = response.text().await?;
+            let body = response.text().await.unwrap_or_default();
             error!("Meilisearch error {}: {}", status, body);
-            anyhow::bail!("Failed to index document: {}", status);
+            return Err(CrawlError::Index { status, body });
         }
 
-        debug!("Successfully indexed: {}", document.url);
+        let task: TaskEnqueued = response.json().await.map_err(CrawlError::IndexRequest)?;
+        debug!("Sent {} documents as task {}", documents.len(), task.task_uid);
 
-        Ok(())
+        Ok(task.task_uid)
     }
 
-    fn generate_id(&self, url: &str) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(url.as_bytes());
-        format!("{:x}", hasher.finalize())
+    pub async fn delete_document(&self, index: &str, document_id: &str) -> Result<(), CrawlError> {
+        let url = format!("{}/indexes/{}/documents/{}", self.base_url, index, document_id);
+
+        let response = self.client
+            .delete(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await
+            .map_err(CrawlError::IndexRequest)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            error!("Meilisearch error {}: {}", status, body);
+            return Err(CrawlError::Index { status, body });
+        }
+
+        // 202 only means the deletion was queued; the URL may only count as removed once
+        // the document is really gone
+        let task: TaskEnqueued = response.json().await.map_err(CrawlError::IndexRequest)?;
+        self.wait_for_task(task.task_uid, DELETE_TASK_TIMEOUT).await?;
+
+        debug!("Deleted document {} from {}", document_id, index);
+
+        Ok(())
     }
 
-    pub async fn create_index_if_not_exists(&self) -> Result<()> {
+    async fn create_index_if_not_exists(&self, index: &str) -> Result<()> {
         let url = format!("{}/indexes", self.base_url);
 
         let index_config = json!({
-            "uid": "web_pages",
+            "uid": index,
             "primaryKey": "id"
         });
 
@@ -121,7 +268,7 @@ impl IndexerClient {
 
         // 201 = created, 202 = already exists
         if response.status().as_u16() == 201 || response.status().as_u16() == 202 {
-            debug!("Index created or already exists");
+            debug!("Index {} created or already exists", index);
             Ok(())
         } else {
             let status = response.status();
@@ -130,4 +277,252 @@ impl IndexerClient {
             anyhow::bail!("Failed to create index: {}", status);
         }
     }
+
+    // Brings the index settings in line with index_config.json, only sending what differs
+    async fn sync_settings(&self, index: &str) -> Result<()> {
+        let desired = self
+            .settings
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("Index settings must be a JSON object"))?;
+        validate_attributes(desired)?;
+
+        let url = format!("{}/indexes/{}/settings", self.base_url, index);
+        let response = self.client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            anyhow::bail!("Failed to read index settings {}: {}", status, body);
+        }
+
+        let current: Value = response.json().await?;
+        let changes = desired
+            .iter()
+            .filter(|(key, value)| !setting_matches(key, value, &current[key.as_str()]))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Map<_, _>>();
+
+        if changes.is_empty() {
+            debug!("Settings for {} are up to date", index);
+            return Ok(());
+        }
+
+        info!("Updating {} settings: {}", index, changes.keys().cloned().collect::<Vec<_>>().join(", "));
+
+        let response = self.client
+            .patch(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&changes)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            anyhow::bail!("Failed to update index settings {}: {}", status, body);
+        }
+
+        let task: TaskEnqueued = response.json().await?;
+        Ok(self.wait_for_task(task.task_uid, SETTINGS_TASK_TIMEOUT).await?)
+    }
+
+    async fn wait_for_task(&self, uid: u64, timeout: Duration) -> Result<(), CrawlError> {
+        let url = format!("{}/tasks/{}", self.base_url, uid);
+        let started = Instant::now();
+
+        loop {
+            let response = self.client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .send()
+                .await
+                .map_err(CrawlError::IndexRequest)?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                return Err(CrawlError::Index { status, body });
+            }
+
+            let task: TaskStatus = response.json().await.map_err(CrawlError::IndexRequest)?;
+            match task.status.as_str() {
+                "succeeded" => return Ok(()),
+                "failed" | "canceled" => {
+                    return Err(CrawlError::IndexTask {
+                        uid,
+                        message: task
+                            .error
+                            .map(|error| error.message)
+                            .unwrap_or_else(|| task.status.clone()),
+                    })
+                }
+                _ if started.elapsed() >= timeout => return Err(CrawlError::IndexTimeout { uid }),
+                _ => tokio::time::sleep(Duration::from_millis(500)).await,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SearchBackend for IndexerClient {
+    async fn ensure_schema(&self, index: &str) -> Result<()> {
+        self.create_index_if_not_exists(index).await?;
+        self.sync_settings(index).await
+    }
+
+    async fn index(&self, index: &str, url_id: i32, document: Document) -> Result<(), CrawlError> {
+        self.queue_document(index, url_id, document).await;
+        Ok(())
+    }
+
+    async fn delete(&self, index: &str, document_id: &str) -> Result<(), CrawlError> {
+        self.delete_document(index, document_id).await
+    }
+
+    // Sends whatever is queued, called on a timer and at shutdown
+    async fn flush(&self) -> Result<(), CrawlError> {
+        let batches = mem::take(&mut *self.pending.lock().unwrap());
+        for (index, batch) in batches {
+            if !batch.documents.is_empty() {
+                self.send_batch(&index, batch).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn poll_outcomes(&self) -> Result<Vec<IndexOutcome>, CrawlError> {
+        self.poll_tasks().await
+    }
+
+    // Whether any sent batch is still waiting on Meilisearch
+    fn has_pending(&self) -> bool {
+        !self.enqueued.lock().unwrap().is_empty()
+    }
+}
+
+pub fn load_settings(path: Option<&str>) -> Result<Value> {
+    let settings = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?,
+        None => DEFAULT_SETTINGS.to_string(),
+    };
+
+    Ok(serde_json::from_str(&settings)?)
+}
+
+// Catch settings that reference attributes our documents never contain
+fn validate_attributes(settings: &Map<String, Value>) -> Result<()> {
+    let document = serde_json::to_value(Document::default())?;
+    let fields = document.as_object().map(|fields| fields.keys().collect::<Vec<_>>()).unwrap_or_default();
+
+    for setting in ATTRIBUTE_SETTINGS {
+        let Some(attributes) = settings.get(setting).and_then(Value::as_array) else {
+            continue;
+        };
+
+        for attribute in attributes.iter().filter_map(Value::as_str) {
+            if attribute != "*" && !fields.iter().any(|field| *field == attribute) {
+                anyhow::bail!("{} lists '{}', which is not a document field", setting, attribute);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Nested settings like typoTolerance may be given partially, so only the keys we set are
+// compared. Synonyms are data rather than a settings group and must match exactly.
+fn setting_matches(key: &str, desired: &Value, current: &Value) -> bool {
+    match (desired, current) {
+        (Value::Object(desired), Value::Object(current)) if key != "synonyms" => desired
+            .iter()
+            .all(|(key, value)| setting_matches(key, value, current.get(key).unwrap_or(&Value::Null))),
+        _ => desired == current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn client_with_task(server: &MockServer, task: Value) -> IndexerClient {
+        Mock::given(method("DELETE"))
+            .and(path("/indexes/web_pages/documents/abc"))
+            .respond_with(ResponseTemplate::new(202).set_body_json(json!({ "taskUid": 7 })))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tasks/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(task))
+            .mount(server)
+            .await;
+
+        let batch = BatchConfig {
+            max_documents: 10,
+            max_bytes: 1024,
+        };
+        IndexerClient::new(server.uri(), String::new(), json!({}), batch)
+    }
+
+    #[tokio::test]
+    async fn delete_waits_for_its_task() {
+        let server = MockServer::start().await;
+        let client = client_with_task(&server, json!({ "uid": 7, "status": "succeeded" })).await;
+        client.delete_document("web_pages", "abc").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_delete_task_is_an_error() {
+        let server = MockServer::start().await;
+        let task = json!({ "uid": 7, "status": "failed", "error": { "message": "index not found" } });
+        let client = client_with_task(&server, task).await;
+
+        let error = client.delete_document("web_pages", "abc").await.unwrap_err();
+        assert!(matches!(error, CrawlError::IndexTask { uid: 7, ref message } if message == "index not found"));
+    }
+
+    fn settings(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn attributes_must_be_document_fields() {
+        let default = serde_json::from_str::<Value>(DEFAULT_SETTINGS).unwrap();
+        validate_attributes(&settings(default)).unwrap();
+        validate_attributes(&settings(json!({ "displayedAttributes": ["*"], "sortableAttributes": ["timestamp"] }))).unwrap();
+
+        // The index used to display a `snippet` that documents never had
+        let error = validate_attributes(&settings(json!({ "displayedAttributes": ["title", "snippet"] }))).unwrap_err();
+        assert!(error.to_string().contains("'snippet'"), "{}", error);
+        assert!(validate_attributes(&settings(json!({ "filterableAttributes": ["*", "lang"] }))).is_err());
+    }
+
+    #[test]
+    fn nested_settings_match_on_the_keys_given() {
+        let current = json!({
+            "enabled": true,
+            "minWordSizeForTypos": { "oneTypo": 5, "twoTypos": 9 },
+            "disableOnWords": []
+        });
+        assert!(setting_matches("typoTolerance", &json!({ "minWordSizeForTypos": { "oneTypo": 5 } }), &current));
+        assert!(!setting_matches("typoTolerance", &json!({ "minWordSizeForTypos": { "oneTypo": 4 } }), &current));
+        assert!(!setting_matches("typoTolerance", &json!({ "disableOnAttributes": ["url"] }), &current));
+        assert!(setting_matches("searchableAttributes", &json!(["title", "content"]), &json!(["title", "content"])));
+        assert!(!setting_matches("searchableAttributes", &json!(["title"]), &json!(["title", "content"])));
+    }
+
+    #[test]
+    fn synonyms_must_match_exactly() {
+        let current = json!({ "phone": ["mobile"], "tv": ["television"] });
+        assert!(setting_matches("synonyms", &json!({ "phone": ["mobile"], "tv": ["television"] }), &current));
+        // A subset would count as matching for other objects, but leaves a stale synonym behind
+        assert!(!setting_matches("synonyms", &json!({ "phone": ["mobile"] }), &current));
+    }
 }
@@ -1,15 +1,52 @@
 use anyhow::Result;
 use sqlx::PgPool;
 use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{info, error};
-use tracing_subscriber;
 
+mod charset;
 mod crawler;
+mod decompress;
+mod error;
+mod feed;
 mod fetcher;
 mod parser;
 mod indexer_client;
+mod normalize;
+#[cfg(test)]
+mod memory_backend;
+mod politeness;
+mod recrawl;
+mod retry;
+mod robots;
+mod routing;
+mod scope;
+mod search_backend;
+mod simhash;
+mod sitemap;
+mod stats;
+#[cfg(feature = "tantivy")]
+mod tantivy_backend;
 
-use crawler::Crawler;
+use crawler::{Crawler, CrawlerConfig};
+use fetcher::BodyLimits;
+use indexer_client::{load_settings, BatchConfig, IndexerClient};
+use normalize::{TrailingSlash, UrlNormalizer, DEFAULT_STRIP_PARAMS};
+use recrawl::RecrawlPolicy;
+use retry::RetryPolicy;
+use routing::IndexRouter;
+use scope::CrawlScope;
+use search_backend::SearchBackend;
+use simhash::NearDuplicatePolicy;
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,30 +57,115 @@ async fn main() -> Result<()> {
         )
         .init();
 
+    // Index settings come from crawler/index_config.json unless overridden
+    let index_settings = load_settings(env::var("MEILISEARCH_SETTINGS_PATH").ok().as_deref())?;
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("search") {
+        return search(&args[1..], &index_settings);
+    }
+
     info!("Starting search crawler...");
 
     // Get environment variables
     let database_url = env::var("DATABASE_URL")
         .expect("DATABASE_URL must be set");
-    let meilisearch_url = env::var("MEILISEARCH_URL")
-        .expect("MEILISEARCH_URL must be set");
-    let meilisearch_key = env::var("MEILISEARCH_KEY")
-        .expect("MEILISEARCH_KEY must be set");
-    
-    let concurrency: usize = env::var("CRAWL_CONCURRENCY")
-        .unwrap_or_else(|_| "100".to_string())
-        .parse()
-        .unwrap_or(100);
-    
-    let delay_ms: u64 = env::var("CRAWL_DELAY_MS")
-        .unwrap_or_else(|_| "1000".to_string())
-        .parse()
-        .unwrap_or(1000);
-    
-    let max_depth: i32 = env::var("MAX_DEPTH")
-        .unwrap_or_else(|_| "3".to_string())
-        .parse()
-        .unwrap_or(3);
+
+    let concurrency: usize = env_or("CRAWL_CONCURRENCY", 100);
+    let delay_ms: u64 = env_or("CRAWL_DELAY_MS", 1000);
+    let max_per_host: usize = env_or("CRAWL_MAX_PER_HOST", 2);
+    let max_depth: i32 = env_or("MAX_DEPTH", 3);
+    let body_limits = BodyLimits {
+        max_body_bytes: env_or("MAX_BODY_BYTES", 10 * 1024 * 1024),
+        max_decompressed_bytes: env_or("MAX_DECOMPRESSED_BYTES", 50 * 1024 * 1024),
+    };
+
+    // Identifies this process on leased rows; defaults to host name and pid
+    let worker_id = env::var("WORKER_ID").unwrap_or_else(|_| {
+        let host = env::var("HOSTNAME").unwrap_or_else(|_| "crawler".to_string());
+        format!("{}-{}", host, std::process::id())
+    });
+    let lease_secs: u64 = env_or("LEASE_SECS", 600);
+
+    let retry = RetryPolicy {
+        max_errors: env_or("RETRY_MAX_ERRORS", 5),
+        base_delay: Duration::from_secs(env_or("RETRY_BASE_SECS", 60)),
+        max_delay: Duration::from_secs(env_or("RETRY_MAX_SECS", 6 * 60 * 60)),
+    };
+
+    let recrawl = RecrawlPolicy {
+        min_interval: Duration::from_secs(env_or("RECRAWL_MIN_SECS", 60 * 60)),
+        max_interval: Duration::from_secs(env_or("RECRAWL_MAX_SECS", 30 * 24 * 60 * 60)),
+    };
+
+    // What to do with pages whose text nearly matches an earlier page: skip, collapse or off
+    let near_duplicates: NearDuplicatePolicy = env::var("NEAR_DUPLICATE_POLICY")
+        .unwrap_or_else(|_| "skip".to_string())
+        .parse()?;
+
+    // Seeds and discovered links are normalized before they are enqueued
+    let normalizer = UrlNormalizer::new(
+        &env::var("URL_STRIP_PARAMS").unwrap_or_else(|_| DEFAULT_STRIP_PARAMS.to_string()),
+        env::var("URL_TRAILING_SLASH").unwrap_or_else(|_| "keep".to_string()).parse::<TrailingSlash>()?,
+    );
+    let seeds: Vec<String> = env::var("SEED_URLS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    // Which discovered links are followed, e.g. SCOPE_ALLOWED_SUFFIXES=bd for Bengali news sites
+    let scope = CrawlScope::parse(
+        env_or("SCOPE_SAME_HOST", false),
+        &env::var("SCOPE_ALLOWED_SUFFIXES").unwrap_or_default(),
+        &env::var("SCOPE_DENIED_DOMAINS").unwrap_or_default(),
+        &env::var("SCOPE_INCLUDE").unwrap_or_default(),
+        &env::var("SCOPE_EXCLUDE").unwrap_or_default(),
+        env_or("SCOPE_MAX_URLS_PER_HOST", 0),
+    )?;
+
+    // Documents go to INDEX_NAME unless an INDEX_ROUTES rule sends them elsewhere
+    let router = IndexRouter::parse(
+        env::var("INDEX_NAME").unwrap_or_else(|_| "web_pages".to_string()),
+        &env::var("INDEX_ROUTES").unwrap_or_default(),
+    )?;
+
+    let index_flush_interval = Duration::from_secs(env_or("INDEX_FLUSH_SECS", 5));
+    let sitemap_interval = Duration::from_secs(env_or("SITEMAP_INTERVAL_SECS", 24 * 60 * 60));
+    let feed_poll = RecrawlPolicy {
+        min_interval: Duration::from_secs(env_or("FEED_MIN_SECS", 5 * 60)),
+        max_interval: Duration::from_secs(env_or("FEED_MAX_SECS", 6 * 60 * 60)),
+    };
+
+    let backend: Arc<dyn SearchBackend> = match env::var("SEARCH_BACKEND").as_deref() {
+        Ok("meilisearch") | Err(_) => {
+            let meilisearch_url = env::var("MEILISEARCH_URL")
+                .expect("MEILISEARCH_URL must be set");
+            let meilisearch_key = env::var("MEILISEARCH_KEY")
+                .expect("MEILISEARCH_KEY must be set");
+            let index_batch = BatchConfig {
+                max_documents: env_or("INDEX_BATCH_SIZE", 500),
+                max_bytes: env_or("INDEX_BATCH_BYTES", 10 * 1024 * 1024),
+            };
+
+            Arc::new(IndexerClient::new(
+                meilisearch_url,
+                meilisearch_key,
+                index_settings,
+                index_batch,
+            ))
+        }
+        #[cfg(feature = "tantivy")]
+        Ok("tantivy") => Arc::new(tantivy_backend::TantivyBackend::new(
+            tantivy_index_dir(),
+            index_settings,
+        )),
+        #[cfg(not(feature = "tantivy"))]
+        Ok("tantivy") => anyhow::bail!("SEARCH_BACKEND=tantivy needs a build with `--features tantivy`"),
+        Ok(other) => anyhow::bail!("Unknown SEARCH_BACKEND '{}'", other),
+    };
 
     // Connect to database
     info!("Connecting to database...");
@@ -53,11 +175,26 @@ async fn main() -> Result<()> {
     // Create crawler instance
     let crawler = Crawler::new(
         pool,
-        meilisearch_url,
-        meilisearch_key,
-        concurrency,
-        delay_ms,
-        max_depth,
+        backend,
+        CrawlerConfig {
+            concurrency,
+            delay_ms,
+            max_per_host,
+            max_depth,
+            body_limits,
+            worker_id,
+            lease_secs,
+            retry,
+            recrawl,
+            near_duplicates,
+            normalizer,
+            scope,
+            seeds,
+            router,
+            index_flush_interval,
+            sitemap_interval,
+            feed_poll,
+        },
     );
 
     // Run crawler
@@ -69,3 +206,45 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+// `search-crawler search <query> [--index name] [--limit n]` queries the local Tantivy index
+#[cfg(feature = "tantivy")]
+fn search(args: &[String], settings: &serde_json::Value) -> Result<()> {
+    let mut index = env::var("INDEX_NAME").unwrap_or_else(|_| "web_pages".to_string());
+    let mut limit = 10;
+    let mut terms = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--index" => {
+                index = args.next().ok_or_else(|| anyhow::anyhow!("--index needs a value"))?.clone();
+            }
+            "--limit" => {
+                limit = args.next().ok_or_else(|| anyhow::anyhow!("--limit needs a value"))?.parse()?;
+            }
+            _ => terms.push(arg.as_str()),
+        }
+    }
+    if terms.is_empty() {
+        anyhow::bail!("Usage: search-crawler search <query> [--index name] [--limit n]");
+    }
+
+    let hits = tantivy_backend::search(&tantivy_index_dir(), &index, settings, &terms.join(" "), limit)?;
+    for hit in &hits {
+        println!("{}\n  {}\n  {}\n", hit.title, hit.url, hit.description);
+    }
+    println!("{} results", hits.len());
+
+    Ok(())
+}
+
+#[cfg(not(feature = "tantivy"))]
+fn search(_args: &[String], _settings: &serde_json::Value) -> Result<()> {
+    anyhow::bail!("The search command needs a build with `--features tantivy`")
+}
+
+#[cfg(feature = "tantivy")]
+fn tantivy_index_dir() -> std::path::PathBuf {
+    env::var("TANTIVY_INDEX_DIR").unwrap_or_else(|_| "./index".to_string()).into()
+}
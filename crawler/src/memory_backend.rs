@@ -0,0 +1,67 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error::CrawlError;
+use crate::search_backend::{Document, IndexOutcome, SearchBackend};
+
+// Keeps documents in memory so tests can run the crawler without Meilisearch
+#[derive(Default)]
+pub struct MemoryBackend {
+    // index -> document id -> document
+    indexes: Mutex<HashMap<String, HashMap<String, Document>>>,
+    settled: Mutex<Vec<IndexOutcome>>,
+}
+
+impl MemoryBackend {
+    pub fn documents(&self, index: &str) -> Vec<Document> {
+        self.indexes
+            .lock()
+            .unwrap()
+            .get(index)
+            .map(|documents| documents.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl SearchBackend for MemoryBackend {
+    async fn ensure_schema(&self, index: &str) -> Result<()> {
+        self.indexes.lock().unwrap().entry(index.to_string()).or_default();
+        Ok(())
+    }
+
+    async fn index(&self, index: &str, url_id: i32, document: Document) -> Result<(), CrawlError> {
+        self.indexes
+            .lock()
+            .unwrap()
+            .entry(index.to_string())
+            .or_default()
+            .insert(document.id.clone(), document);
+        self.settled.lock().unwrap().push(IndexOutcome {
+            url_ids: vec![url_id],
+            result: Ok(()),
+        });
+        Ok(())
+    }
+
+    async fn delete(&self, index: &str, document_id: &str) -> Result<(), CrawlError> {
+        if let Some(documents) = self.indexes.lock().unwrap().get_mut(index) {
+            documents.remove(document_id);
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), CrawlError> {
+        Ok(())
+    }
+
+    async fn poll_outcomes(&self) -> Result<Vec<IndexOutcome>, CrawlError> {
+        Ok(std::mem::take(&mut *self.settled.lock().unwrap()))
+    }
+
+    fn has_pending(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,223 @@
+use std::str::FromStr;
+use url::Url;
+
+// Query parameters that only track where a visitor came from
+pub const DEFAULT_STRIP_PARAMS: &str =
+    "utm_*,gclid,dclid,fbclid,msclkid,yclid,mc_cid,mc_eid,_ga,_gl,igshid,ref_src";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    Keep,
+    // `/a/` becomes `/a`
+    Strip,
+    // `/a` becomes `/a/`, unless the last segment looks like a file (`/a.html`)
+    Add,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "keep" => Ok(Self::Keep),
+            "strip" => Ok(Self::Strip),
+            "add" => Ok(Self::Add),
+            other => anyhow::bail!("Unknown trailing slash policy '{}'", other),
+        }
+    }
+}
+
+// Rewrites URLs into one canonical spelling so variants of a page share a row in `urls`
+#[derive(Debug, Clone)]
+pub struct UrlNormalizer {
+    // Exact parameter names, or prefixes when ending in `*`
+    strip_params: Vec<String>,
+    trailing_slash: TrailingSlash,
+}
+
+impl UrlNormalizer {
+    // `strip_params` is a comma separated list like DEFAULT_STRIP_PARAMS
+    pub fn new(strip_params: &str, trailing_slash: TrailingSlash) -> Self {
+        Self {
+            strip_params: strip_params
+                .split(',')
+                .map(|param| param.trim().to_lowercase())
+                .filter(|param| !param.is_empty())
+                .collect(),
+            trailing_slash,
+        }
+    }
+
+    // Returns None for URLs that are not http(s) or do not parse
+    pub fn normalize(&self, url: &str) -> Option<String> {
+        // Parsing already lowercases the host, drops the default port and resolves
+        // `.` and `..` path segments
+        let mut url = Url::parse(url.trim()).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        url.set_fragment(None);
+
+        // `example.com.` is the same host as `example.com`
+        if let Some(host) = url.host_str().filter(|host| host.ends_with('.')) {
+            let host = host.trim_end_matches('.').to_string();
+            url.set_host(Some(&host)).ok()?;
+        }
+
+        let mut path = normalize_percent_encoding(url.path());
+        match self.trailing_slash {
+            TrailingSlash::Keep => {}
+            TrailingSlash::Strip => {
+                while path.len() > 1 && path.ends_with('/') {
+                    path.pop();
+                }
+            }
+            TrailingSlash::Add => {
+                let last_segment = path.rsplit('/').next().unwrap_or_default();
+                if !path.ends_with('/') && !last_segment.contains('.') {
+                    path.push('/');
+                }
+            }
+        }
+        url.set_path(&path);
+
+        let mut params = url
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(normalize_percent_encoding)
+            .filter(|param| !self.is_stripped(param.split('=').next().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        params.sort();
+        if params.is_empty() {
+            url.set_query(None);
+        } else {
+            url.set_query(Some(&params.join("&")));
+        }
+
+        Some(url.to_string())
+    }
+
+    fn is_stripped(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.strip_params.iter().any(|param| match param.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == *param,
+        })
+    }
+}
+
+// Uppercases percent escapes and decodes the ones that stand for unreserved characters,
+// so `%7e`, `%7E` and `~` compare equal. Expects the ASCII-only output of `Url`
+fn normalize_percent_encoding(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = String::with_capacity(input.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3).unwrap_or_default();
+            if let Ok(value) = u8::from_str_radix(hex, 16) {
+                if value.is_ascii_alphanumeric() || matches!(value, b'-' | b'.' | b'_' | b'~') {
+                    output.push(value as char);
+                } else {
+                    output.push('%');
+                    output.push_str(&hex.to_uppercase());
+                }
+                i += 3;
+                continue;
+            }
+        }
+        output.push(bytes[i] as char);
+        i += 1;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(trailing_slash: TrailingSlash) -> UrlNormalizer {
+        UrlNormalizer::new(DEFAULT_STRIP_PARAMS, trailing_slash)
+    }
+
+    #[test]
+    fn normalizes_tricky_urls() {
+        let normalizer = normalizer(TrailingSlash::Keep);
+        let cases = [
+            ("HTTP://Example.COM/a", "http://example.com/a"),
+            ("http://example.com:80/a", "http://example.com/a"),
+            ("https://example.com:443/a", "https://example.com/a"),
+            ("https://example.com:8443/a", "https://example.com:8443/a"),
+            ("http://example.com", "http://example.com/"),
+            ("http://example.com./a", "http://example.com/a"),
+            ("http://example.com/a/./b/../c", "http://example.com/a/c"),
+            ("http://example.com/../a", "http://example.com/a"),
+            ("http://example.com/a#section", "http://example.com/a"),
+            ("http://example.com/a?", "http://example.com/a"),
+            ("http://example.com/a?b=2&a=1", "http://example.com/a?a=1&b=2"),
+            ("http://example.com/a?a=2&a=1", "http://example.com/a?a=1&a=2"),
+            ("http://example.com/a?utm_source=x&utm_medium=y", "http://example.com/a"),
+            ("http://example.com/a?id=7&UTM_Campaign=z&fbclid=abc", "http://example.com/a?id=7"),
+            ("http://example.com/a?gclid=1&&q=rust", "http://example.com/a?q=rust"),
+            ("http://example.com/%7euser/%2fdocs", "http://example.com/~user/%2Fdocs"),
+            ("http://example.com/a?q=%e2%9c%93", "http://example.com/a?q=%E2%9C%93"),
+            ("http://example.com/a b", "http://example.com/a%20b"),
+            ("http://bücher.example/", "http://xn--bcher-kva.example/"),
+            ("  http://example.com/a  ", "http://example.com/a"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(normalizer.normalize(input).as_deref(), Some(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn variants_collapse_to_one_url() {
+        let normalizer = normalizer(TrailingSlash::Strip);
+        let variants = [
+            "http://Example.com/a?utm_source=x",
+            "http://example.com/a/",
+            "http://example.com:80/a",
+            "http://example.com/b/../a#top",
+        ];
+
+        for variant in variants {
+            assert_eq!(normalizer.normalize(variant).as_deref(), Some("http://example.com/a"), "{}", variant);
+        }
+    }
+
+    #[test]
+    fn applies_trailing_slash_policy() {
+        let strip = normalizer(TrailingSlash::Strip);
+        assert_eq!(strip.normalize("http://example.com/a//").as_deref(), Some("http://example.com/a"));
+        assert_eq!(strip.normalize("http://example.com/").as_deref(), Some("http://example.com/"));
+
+        let add = normalizer(TrailingSlash::Add);
+        assert_eq!(add.normalize("http://example.com/a").as_deref(), Some("http://example.com/a/"));
+        assert_eq!(add.normalize("http://example.com/a.html").as_deref(), Some("http://example.com/a.html"));
+        assert_eq!(add.normalize("http://example.com/a?x=1").as_deref(), Some("http://example.com/a/?x=1"));
+    }
+
+    #[test]
+    fn rejects_non_http_urls() {
+        let normalizer = normalizer(TrailingSlash::Keep);
+        assert_eq!(normalizer.normalize("mailto:someone@example.com"), None);
+        assert_eq!(normalizer.normalize("javascript:void(0)"), None);
+        assert_eq!(normalizer.normalize("ftp://example.com/file"), None);
+        assert_eq!(normalizer.normalize("not a url"), None);
+    }
+
+    #[test]
+    fn custom_strip_list_replaces_defaults() {
+        let normalizer = UrlNormalizer::new("sessionid, ref*", TrailingSlash::Keep);
+        assert_eq!(
+            normalizer.normalize("http://example.com/a?sessionid=1&referrer=x&utm_source=y").as_deref(),
+            Some("http://example.com/a?utm_source=y"),
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use scraper::{Html, Selector};
+use sha2::{Digest, Sha256};
 use url::Url;
-use anyhow::Result;
 use tracing::debug;
 
+use crate::error::CrawlError;
+
 pub struct Parser;
 
 pub struct ParsedContent {
@@ -11,15 +13,72 @@ pub struct ParsedContent {
     pub description: String,
     pub icon: String,
     pub content: String,
+    pub language: Option<String>,
+    // Absolute URL from <link rel="canonical">
+    pub canonical: Option<String>,
+    // From <meta name="robots">
+    pub robots: RobotsDirectives,
+    // RSS and Atom feeds from <link rel="alternate">
+    pub feeds: Vec<String>,
+    // Links without rel="nofollow"
     pub links: Vec<String>,
 }
 
+// Indexing and link-following directives from meta robots or the X-Robots-Tag header
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RobotsDirectives {
+    pub noindex: bool,
+    pub nofollow: bool,
+}
+
+impl RobotsDirectives {
+    // Comma separated directives such as "noindex, nofollow"; unknown ones are ignored
+    pub fn parse(content: &str) -> Self {
+        let mut directives = Self::default();
+        for directive in content.split(',') {
+            match directive.trim().to_lowercase().as_str() {
+                "noindex" => directives.noindex = true,
+                "nofollow" => directives.nofollow = true,
+                "none" => {
+                    directives.noindex = true;
+                    directives.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+        directives
+    }
+
+    // The most restrictive combination of both
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow,
+        }
+    }
+}
+
+impl ParsedContent {
+    // Fingerprint of everything that ends up in the document or decides its index, so
+    // markup changes, ads or nonces in the raw HTML don't count as a change
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        let language = self.language.as_deref().unwrap_or_default();
+        let canonical = self.canonical.as_deref().unwrap_or_default();
+        for field in [&self.title, &self.name, &self.description, &self.icon, &self.content, language, canonical] {
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
 impl Parser {
     pub fn new() -> Self {
         Self
     }
 
-    pub fn parse(&self, html: &str, base_url: &str) -> Result<ParsedContent> {
+    pub fn parse(&self, html: &str, base_url: &str) -> Result<ParsedContent, CrawlError> {
         let document = Html::parse_document(html);
         let base = Url::parse(base_url)?;
 
@@ -38,6 +97,16 @@ impl Parser {
         // Extract main content
         let content = self.extract_content(&document);
 
+        // Extract declared language
+        let language = self.extract_language(&document);
+
+        // Extract canonical URL and indexing directives
+        let canonical = self.extract_canonical(&document, &base);
+        let robots = self.extract_robots(&document);
+
+        // Extract advertised feeds
+        let feeds = self.extract_feeds(&document, &base);
+
         // Extract links
         let links = self.extract_links(&document, &base);
 
@@ -50,6 +119,10 @@ impl Parser {
             description,
             icon,
             content,
+            language,
+            canonical,
+            robots,
+            feeds,
             links,
         })
     }
@@ -190,11 +263,77 @@ impl Parser {
             .collect()
     }
 
+    fn extract_language(&self, document: &Html) -> Option<String> {
+        let lang_selectors = [
+            ("html[lang]", "lang"),
+            ("meta[http-equiv='content-language']", "content"),
+            ("meta[property='og:locale']", "content"),
+        ];
+
+        for (selector_str, attr) in lang_selectors {
+            if let Ok(selector) = Selector::parse(selector_str) {
+                if let Some(element) = document.select(&selector).next() {
+                    if let Some(lang) = element.value().attr(attr) {
+                        let lang = lang.trim();
+                        if !lang.is_empty() {
+                            return Some(lang.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn extract_canonical(&self, document: &Html, base: &Url) -> Option<String> {
+        let link_selector = Selector::parse("link[rel][href]").unwrap();
+
+        document
+            .select(&link_selector)
+            .find(|el| has_rel(el.value().attr("rel"), "canonical"))
+            .and_then(|el| el.value().attr("href"))
+            .and_then(|href| base.join(href.trim()).ok())
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .map(|url| url.to_string())
+    }
+
+    fn extract_robots(&self, document: &Html) -> RobotsDirectives {
+        let robots_selector = Selector::parse("meta[name][content]").unwrap();
+
+        document
+            .select(&robots_selector)
+            .filter(|el| el.value().attr("name").is_some_and(|name| name.trim().eq_ignore_ascii_case("robots")))
+            .filter_map(|el| el.value().attr("content"))
+            .map(RobotsDirectives::parse)
+            .fold(RobotsDirectives::default(), RobotsDirectives::merge)
+    }
+
+    fn extract_feeds(&self, document: &Html, base: &Url) -> Vec<String> {
+        let link_selector = Selector::parse("link[rel][type][href]").unwrap();
+
+        document
+            .select(&link_selector)
+            .filter(|el| has_rel(el.value().attr("rel"), "alternate"))
+            .filter(|el| {
+                el.value().attr("type").is_some_and(|media_type| {
+                    let media_type = media_type.trim().to_lowercase();
+                    media_type == "application/rss+xml" || media_type == "application/atom+xml"
+                })
+            })
+            .filter_map(|el| el.value().attr("href"))
+            .filter_map(|href| base.join(href.trim()).ok())
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .map(|url| url.to_string())
+            .collect()
+    }
+
     fn extract_links(&self, document: &Html, base: &Url) -> Vec<String> {
         let link_selector = Selector::parse("a[href]").unwrap();
         
         document
             .select(&link_selector)
+            .filter(|el| !has_rel(el.value().attr("rel"), "nofollow"))
             .filter_map(|el| el.value().attr("href"))
             .filter_map(|href| base.join(href).ok())
             .filter(|url| {
@@ -210,3 +349,61 @@ impl Parser {
             .collect()
     }
 }
+
+// rel holds a space separated list of link types, e.g. rel="nofollow noopener"
+fn has_rel(rel: Option<&str>, link_type: &str) -> bool {
+    rel.is_some_and(|rel| rel.split_whitespace().any(|value| value.eq_ignore_ascii_case(link_type)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &str, body: &str) -> ParsedContent {
+        let html = format!("<html><head>{}</head><body>{}</body></html>", head, body);
+        Parser::new().parse(&html, "https://example.com/news/story?page=2").unwrap()
+    }
+
+    #[test]
+    fn relative_canonical_is_resolved() {
+        let parsed = parse(r#"<link rel="Canonical" href=" /news/story ">"#, "");
+        assert_eq!(parsed.canonical.as_deref(), Some("https://example.com/news/story"));
+
+        let parsed = parse(r#"<link rel="alternate canonical" href="../about">"#, "");
+        assert_eq!(parsed.canonical.as_deref(), Some("https://example.com/about"));
+
+        assert!(parse(r#"<link rel="stylesheet" href="/style.css">"#, "").canonical.is_none());
+    }
+
+    #[test]
+    fn non_http_canonical_is_dropped() {
+        assert!(parse(r#"<link rel="canonical" href="javascript:void(0)">"#, "").canonical.is_none());
+        assert!(parse(r#"<link rel="canonical" href="ftp://example.com/story">"#, "").canonical.is_none());
+    }
+
+    #[test]
+    fn meta_robots_directives() {
+        let robots = parse(r#"<meta name="ROBOTS" content="none">"#, "").robots;
+        assert_eq!(robots, RobotsDirectives { noindex: true, nofollow: true });
+
+        let robots = parse(r#"<meta name="robots" content="NoIndex, follow"><meta name="robots" content="nofollow">"#, "").robots;
+        assert_eq!(robots, RobotsDirectives { noindex: true, nofollow: true });
+
+        // Other crawlers' and other meta tags don't apply
+        let robots = parse(r#"<meta name="otherbot" content="noindex"><meta name="description" content="none">"#, "").robots;
+        assert_eq!(robots, RobotsDirectives::default());
+    }
+
+    #[test]
+    fn nofollow_links_are_excluded() {
+        let parsed = parse(
+            "",
+            r##"<a href="/a#top">A</a>
+                <a href="/b" rel="nofollow noopener">B</a>
+                <a href="https://other.example/c" rel="NOFOLLOW">C</a>
+                <a href="/d" rel="noopener noreferrer">D</a>
+                <a href="mailto:news@example.com">Mail</a>"##,
+        );
+        assert_eq!(parsed.links, ["https://example.com/a", "https://example.com/d"]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep_until, Instant};
+
+// Ignore absurd Crawl-delay values so one host cannot stall a whole batch
+pub const MAX_CRAWL_DELAY: Duration = Duration::from_secs(30);
+
+// Hosts tracked before idle ones are swept out
+const MAX_TRACKED_HOSTS: usize = 1_024;
+
+struct HostState {
+    permits: Arc<Semaphore>,
+    next_slot: Mutex<Instant>,
+}
+
+impl HostState {
+    // Nothing in flight and no start time reserved, so forgetting the host changes nothing
+    fn is_idle(&self, max_in_flight: usize) -> bool {
+        self.permits.available_permits() == max_in_flight && *self.next_slot.lock().unwrap() <= Instant::now()
+    }
+}
+
+// Held for the duration of a request, frees the host's in-flight slot on drop
+pub struct HostPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+pub struct HostScheduler {
+    min_interval: Duration,
+    max_in_flight: usize,
+    hosts: Mutex<HashMap<String, Arc<HostState>>>,
+}
+
+impl HostScheduler {
+    pub fn new(min_interval: Duration, max_in_flight: usize) -> Self {
+        Self {
+            min_interval,
+            max_in_flight: max_in_flight.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn acquire(&self, host: &str, crawl_delay: Option<Duration>) -> HostPermit {
+        let state = {
+            let mut hosts = self.hosts.lock().unwrap();
+            // States are only handed out under this lock, one nobody else holds can't be in use
+            if hosts.len() >= MAX_TRACKED_HOSTS && !hosts.contains_key(host) {
+                hosts.retain(|_, state| Arc::strong_count(state) > 1 || !state.is_idle(self.max_in_flight));
+            }
+
+            Arc::clone(hosts.entry(host.to_string()).or_insert_with(|| {
+                Arc::new(HostState {
+                    permits: Arc::new(Semaphore::new(self.max_in_flight)),
+                    next_slot: Mutex::new(Instant::now()),
+                })
+            }))
+        };
+
+        let permit = Arc::clone(&state.permits)
+            .acquire_owned()
+            .await
+            .expect("host semaphore is never closed");
+
+        let interval = crawl_delay
+            .map(|delay| delay.min(MAX_CRAWL_DELAY))
+            .unwrap_or_default()
+            .max(self.min_interval);
+
+        // Reserve the next start time for this host, then wait for it
+        let start_at = {
+            let mut next_slot = state.next_slot.lock().unwrap();
+            let start_at = (*next_slot).max(Instant::now());
+            *next_slot = start_at + interval;
+            start_at
+        };
+        sleep_until(start_at).await;
+
+        HostPermit { _permit: permit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn idle_hosts_are_forgotten() {
+        let scheduler = HostScheduler::new(Duration::ZERO, 1);
+
+        // Still in flight, so it survives the sweep
+        let busy = scheduler.acquire("busy.example", None).await;
+        for i in 0..MAX_TRACKED_HOSTS {
+            scheduler.acquire(&format!("host{}.example", i), None).await;
+        }
+
+        let hosts = scheduler.hosts.lock().unwrap();
+        assert!(hosts.len() < MAX_TRACKED_HOSTS);
+        assert!(hosts.contains_key("busy.example"));
+        drop(busy);
+    }
+}
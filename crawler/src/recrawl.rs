@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+// Interval for a page seen for the first time
+const INITIAL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct RecrawlPolicy {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+}
+
+impl RecrawlPolicy {
+    // Halves the interval when the content changed since the last visit and doubles it
+    // when it did not, so pages settle near how often they actually change
+    pub fn next_interval(&self, previous: Option<Duration>, changed: bool) -> Duration {
+        let interval = match previous {
+            None => INITIAL_INTERVAL,
+            Some(previous) if changed => previous / 2,
+            Some(previous) => previous.saturating_mul(2),
+        };
+
+        self.clamp(interval)
+    }
+
+    pub fn clamp(&self, interval: Duration) -> Duration {
+        interval.clamp(self.min_interval, self.max_interval.max(self.min_interval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    const POLICY: RecrawlPolicy = RecrawlPolicy {
+        min_interval: HOUR,
+        max_interval: Duration::from_secs(30 * 24 * 60 * 60),
+    };
+
+    #[test]
+    fn first_visit_uses_the_initial_interval() {
+        assert_eq!(POLICY.next_interval(None, true), INITIAL_INTERVAL);
+        assert_eq!(POLICY.next_interval(None, false), INITIAL_INTERVAL);
+
+        // Still within the bounds
+        let frequent = RecrawlPolicy {
+            min_interval: HOUR,
+            max_interval: 2 * HOUR,
+        };
+        assert_eq!(frequent.next_interval(None, false), 2 * HOUR);
+    }
+
+    #[test]
+    fn changes_halve_and_stability_doubles() {
+        assert_eq!(POLICY.next_interval(Some(8 * HOUR), true), 4 * HOUR);
+        assert_eq!(POLICY.next_interval(Some(8 * HOUR), false), 16 * HOUR);
+    }
+
+    #[test]
+    fn intervals_stay_within_the_bounds() {
+        assert_eq!(POLICY.next_interval(Some(HOUR), true), HOUR);
+        assert_eq!(POLICY.next_interval(Some(20 * 24 * HOUR), false), POLICY.max_interval);
+        assert_eq!(POLICY.next_interval(Some(Duration::MAX), false), POLICY.max_interval);
+        assert_eq!(POLICY.clamp(Duration::ZERO), HOUR);
+        assert_eq!(POLICY.clamp(5 * HOUR), 5 * HOUR);
+    }
+
+    #[test]
+    fn max_below_min_means_min() {
+        let policy = RecrawlPolicy {
+            min_interval: 4 * HOUR,
+            max_interval: HOUR,
+        };
+        assert_eq!(policy.clamp(Duration::ZERO), 4 * HOUR);
+        assert_eq!(policy.clamp(100 * HOUR), 4 * HOUR);
+        assert_eq!(policy.next_interval(Some(4 * HOUR), false), 4 * HOUR);
+    }
+}
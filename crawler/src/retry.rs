@@ -0,0 +1,119 @@
+use rand::Rng;
+use std::time::Duration;
+
+use crate::error::CrawlError;
+use crate::fetcher::FetchError;
+
+// Upper bound for a server-requested Retry-After
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_errors: i32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // Returns when to try again, or None once the URL should be given up on.
+    // `error_count` includes the failure being handled.
+    pub fn next_attempt(&self, error: &CrawlError, error_count: i32) -> Option<Duration> {
+        // An outage of the host's robots.txt is waited out however long it lasts
+        if let CrawlError::Fetch(FetchError::RobotsUnavailable { retry_after }) = error {
+            return Some((*retry_after).min(MAX_RETRY_AFTER));
+        }
+
+        if !error.kind().is_retryable() || error_count >= self.max_errors {
+            return None;
+        }
+
+        // Exponential backoff with jitter over the upper half of the window
+        let exponent = (error_count - 1).clamp(0, 20) as u32;
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay);
+        let backoff = backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+
+        let retry_after = match error {
+            CrawlError::Fetch(FetchError::Status { retry_after, .. }) => *retry_after,
+            _ => None,
+        };
+
+        Some(match retry_after {
+            Some(retry_after) => retry_after.min(MAX_RETRY_AFTER).max(backoff),
+            None => backoff,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_errors: 5,
+        base_delay: Duration::from_secs(60),
+        max_delay: Duration::from_secs(3600),
+    };
+
+    fn status(status: StatusCode, retry_after: Option<Duration>) -> CrawlError {
+        CrawlError::Fetch(FetchError::Status { status, retry_after })
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_cap() {
+        let error = status(StatusCode::SERVICE_UNAVAILABLE, None);
+
+        for (error_count, full) in [(1, 60), (2, 120), (3, 240), (4, 480)] {
+            let delay = POLICY.next_attempt(&error, error_count).unwrap();
+            let full = Duration::from_secs(full);
+            assert!(delay >= full / 2 && delay <= full, "attempt {}: {:?}", error_count, delay);
+        }
+
+        let policy = RetryPolicy {
+            max_errors: 100,
+            ..POLICY
+        };
+        let delay = policy.next_attempt(&error, 50).unwrap();
+        assert!(delay >= POLICY.max_delay / 2 && delay <= POLICY.max_delay, "{:?}", delay);
+    }
+
+    #[test]
+    fn gives_up_after_max_errors_or_on_permanent_errors() {
+        assert!(POLICY.next_attempt(&status(StatusCode::BAD_GATEWAY, None), 5).is_none());
+        assert!(POLICY.next_attempt(&status(StatusCode::NOT_FOUND, None), 1).is_none());
+        assert!(POLICY.next_attempt(&CrawlError::Fetch(FetchError::RobotsBlocked), 1).is_none());
+        assert!(POLICY.next_attempt(&CrawlError::Fetch(FetchError::NotHtml), 1).is_none());
+    }
+
+    #[test]
+    fn longer_retry_after_wins_over_backoff() {
+        let hour = Duration::from_secs(3600);
+        let error = status(StatusCode::TOO_MANY_REQUESTS, Some(hour));
+        assert_eq!(POLICY.next_attempt(&error, 1), Some(hour));
+    }
+
+    #[test]
+    fn shorter_retry_after_still_waits_for_backoff() {
+        let error = status(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(1)));
+        let delay = POLICY.next_attempt(&error, 3).unwrap();
+        assert!(delay >= Duration::from_secs(120), "{:?}", delay);
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let error = status(StatusCode::SERVICE_UNAVAILABLE, Some(Duration::from_secs(365 * 24 * 60 * 60)));
+        assert_eq!(POLICY.next_attempt(&error, 1), Some(MAX_RETRY_AFTER));
+    }
+
+    #[test]
+    fn unavailable_robots_txt_waits_for_the_next_fetch() {
+        let retry_after = Duration::from_secs(45 * 60);
+        let error = CrawlError::Fetch(FetchError::RobotsUnavailable { retry_after });
+        assert_eq!(POLICY.next_attempt(&error, 1), Some(retry_after));
+        // However many times it happened
+        assert_eq!(POLICY.next_attempt(&error, POLICY.max_errors + 10), Some(retry_after));
+    }
+}
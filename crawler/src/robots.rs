@@ -0,0 +1,445 @@
+use reqwest::{Client, StatusCode};
+use robotstxt::{parse_robotstxt, DefaultMatcher, RobotsParseHandler};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::fetcher::read_body_prefix;
+use crate::politeness::MAX_CRAWL_DELAY;
+use crate::sitemap::SitemapSource;
+
+// How long a successfully fetched (or missing) robots.txt is trusted
+const ROBOTS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// Server errors are retried sooner, the host's URLs wait until then
+const ROBOTS_ERROR_TTL: Duration = Duration::from_secs(60 * 60);
+
+// Most origins whose rules are kept; past this the least recently used quarter is dropped
+// and fetched again if needed
+const MAX_CACHED_ORIGINS: usize = 10_000;
+
+// Most of a robots.txt that is parsed, as in Google's crawler
+const MAX_ROBOTS_BYTES: usize = 500 * 1024;
+
+enum RobotsRules {
+    AllowAll,
+    // The fetch failed, so it is unknown what is allowed
+    Unavailable,
+    Body(String),
+}
+
+struct RobotsEntry {
+    rules: RobotsRules,
+    crawl_delay: Option<Duration>,
+    expires_at: Instant,
+}
+
+pub enum RobotsCheck {
+    Allowed { crawl_delay: Option<Duration> },
+    Disallowed,
+    // robots.txt could not be fetched; it is tried again after the given time
+    Unavailable { retry_after: Duration },
+}
+
+struct HostSlot {
+    entry: Arc<tokio::sync::Mutex<Option<RobotsEntry>>>,
+    last_used: Instant,
+}
+
+pub struct RobotsCache {
+    client: Client,
+    user_agent: String,
+    hosts: Mutex<HashMap<String, HostSlot>>,
+    // Sitemaps seen since the last take_sitemaps, with the origin that declared them
+    sitemaps: Mutex<Vec<(String, SitemapSource, String)>>,
+}
+
+impl RobotsCache {
+    pub fn new(client: Client, user_agent: &str) -> Self {
+        // robots.txt groups match on the product token only
+        let user_agent = product_token(user_agent).to_string();
+
+        Self {
+            client,
+            user_agent,
+            hosts: Mutex::new(HashMap::new()),
+            sitemaps: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Sitemaps from robots.txt files fetched since the last call, or `/sitemap.xml` for
+    // hosts whose robots.txt lists none
+    pub fn take_sitemaps(&self) -> Vec<(String, SitemapSource, String)> {
+        std::mem::take(&mut *self.sitemaps.lock().unwrap())
+    }
+
+    pub async fn check(&self, url: &Url) -> RobotsCheck {
+        let origin = url.origin().ascii_serialization();
+
+        // One slot per origin so concurrent tasks wait for a single robots.txt fetch
+        let slot = {
+            let mut hosts = self.hosts.lock().unwrap();
+            if hosts.len() >= MAX_CACHED_ORIGINS && !hosts.contains_key(&origin) {
+                evict_least_recently_used(&mut hosts);
+            }
+
+            let slot = hosts.entry(origin.clone()).or_insert_with(|| HostSlot {
+                entry: Arc::default(),
+                last_used: Instant::now(),
+            });
+            slot.last_used = Instant::now();
+            Arc::clone(&slot.entry)
+        };
+
+        let mut entry = slot.lock().await;
+        let expired = entry
+            .as_ref()
+            .is_none_or(|entry| entry.expires_at <= Instant::now());
+
+        if expired {
+            *entry = Some(self.fetch_rules(&origin).await);
+        }
+
+        let entry = entry.as_ref().unwrap();
+        let allowed = match &entry.rules {
+            RobotsRules::AllowAll => true,
+            RobotsRules::Unavailable => {
+                return RobotsCheck::Unavailable {
+                    retry_after: entry.expires_at.saturating_duration_since(Instant::now()),
+                }
+            }
+            RobotsRules::Body(body) => DefaultMatcher::default()
+                .one_agent_allowed_by_robots(body, &self.user_agent, url.as_str()),
+        };
+
+        if allowed {
+            RobotsCheck::Allowed {
+                crawl_delay: entry.crawl_delay,
+            }
+        } else {
+            RobotsCheck::Disallowed
+        }
+    }
+
+    async fn fetch_rules(&self, origin: &str) -> RobotsEntry {
+        let robots_url = format!("{}/robots.txt", origin);
+        debug!("Fetching robots.txt: {}", robots_url);
+
+        let (rules, ttl) = match self.client.get(&robots_url).send().await {
+            // The client asks for compressed responses, so the body is decoded like a page's.
+            // Only the start of an oversized file is read, and the rules in it still apply
+            Ok(response) if response.status().is_success() => match read_body_prefix(response, MAX_ROBOTS_BYTES).await {
+                Ok(body) => (RobotsRules::Body(String::from_utf8_lossy(&body).into_owned()), ROBOTS_TTL),
+                Err(e) => {
+                    warn!("Failed to read {}: {}", robots_url, e);
+                    (RobotsRules::Unavailable, ROBOTS_ERROR_TTL)
+                }
+            },
+            // Rate limited or server error: the whole site waits until we know its rules
+            Ok(response)
+                if response.status() == StatusCode::TOO_MANY_REQUESTS
+                    || response.status().is_server_error() =>
+            {
+                warn!("robots.txt unavailable for {}: {}", origin, response.status());
+                (RobotsRules::Unavailable, ROBOTS_ERROR_TTL)
+            }
+            // Any other 4xx means there is no robots.txt, so everything is allowed
+            Ok(response) => {
+                debug!("No robots.txt for {} ({})", origin, response.status());
+                (RobotsRules::AllowAll, ROBOTS_TTL)
+            }
+            Err(e) => {
+                warn!("Failed to fetch {}: {}", robots_url, e);
+                (RobotsRules::Unavailable, ROBOTS_ERROR_TTL)
+            }
+        };
+
+        let (crawl_delay, sitemaps) = match &rules {
+            RobotsRules::Body(body) => {
+                let mut directives = DirectivesHandler::new(&self.user_agent);
+                parse_robotstxt(body, &mut directives);
+                (directives.crawl_delay(), directives.sitemaps)
+            }
+            _ => (None, Vec::new()),
+        };
+
+        // Nothing to look for on a host we may not crawl right now
+        if !matches!(rules, RobotsRules::Unavailable) {
+            let mut discovered = self.sitemaps.lock().unwrap();
+            if sitemaps.is_empty() {
+                discovered.push((format!("{}/sitemap.xml", origin), SitemapSource::Guessed, origin.to_string()));
+            }
+            discovered.extend(
+                sitemaps
+                    .into_iter()
+                    .map(|url| (url, SitemapSource::Robots, origin.to_string())),
+            );
+        }
+
+        RobotsEntry {
+            rules,
+            crawl_delay,
+            expires_at: Instant::now() + ttl,
+        }
+    }
+}
+
+// Slots are only handed out under the map's lock, so one nobody else holds is idle and can go
+fn evict_least_recently_used(hosts: &mut HashMap<String, HostSlot>) {
+    let mut idle = hosts
+        .iter()
+        .filter(|(_, slot)| Arc::strong_count(&slot.entry) == 1)
+        .map(|(origin, slot)| (slot.last_used, origin.clone()))
+        .collect::<Vec<_>>();
+    idle.sort_unstable();
+
+    for (_, origin) in idle.into_iter().take(MAX_CACHED_ORIGINS / 4) {
+        hosts.remove(&origin);
+    }
+}
+
+// The matcher ignores non-standard lines and sitemaps, so Crawl-delay (preferring a group
+// naming our agent over the "*" group) and Sitemap lines are collected separately
+struct DirectivesHandler<'a> {
+    user_agent: &'a str,
+    in_specific_group: bool,
+    in_global_group: bool,
+    seen_rule: bool,
+    specific_delay: Option<f64>,
+    global_delay: Option<f64>,
+    sitemaps: Vec<String>,
+}
+
+impl<'a> DirectivesHandler<'a> {
+    fn new(user_agent: &'a str) -> Self {
+        Self {
+            user_agent,
+            in_specific_group: false,
+            in_global_group: false,
+            seen_rule: false,
+            specific_delay: None,
+            global_delay: None,
+            sitemaps: Vec::new(),
+        }
+    }
+
+    // Capped before converting, a huge value would not fit a Duration
+    fn crawl_delay(&self) -> Option<Duration> {
+        self.specific_delay
+            .or(self.global_delay)
+            .filter(|secs| secs.is_finite() && *secs > 0.0)
+            .map(|secs| Duration::from_secs_f64(secs.min(MAX_CRAWL_DELAY.as_secs_f64())))
+    }
+}
+
+impl RobotsParseHandler for DirectivesHandler<'_> {
+    fn handle_robots_start(&mut self) {}
+
+    fn handle_robots_end(&mut self) {}
+
+    fn handle_user_agent(&mut self, _line_num: u32, user_agent: &str) {
+        // A user-agent line after rules starts a new group
+        if self.seen_rule {
+            self.in_specific_group = false;
+            self.in_global_group = false;
+            self.seen_rule = false;
+        }
+
+        if user_agent.trim() == "*" {
+            self.in_global_group = true;
+        } else if user_agent.trim().eq_ignore_ascii_case(self.user_agent) {
+            self.in_specific_group = true;
+        }
+    }
+
+    fn handle_allow(&mut self, _line_num: u32, _value: &str) {
+        self.seen_rule = true;
+    }
+
+    fn handle_disallow(&mut self, _line_num: u32, _value: &str) {
+        self.seen_rule = true;
+    }
+
+    // Sitemap lines apply to the whole file, whichever group they appear in
+    fn handle_sitemap(&mut self, _line_num: u32, value: &str) {
+        let value = value.trim();
+        if !value.is_empty() {
+            self.sitemaps.push(value.to_string());
+        }
+    }
+
+    fn handle_unknown_action(&mut self, _line_num: u32, action: &str, value: &str) {
+        self.seen_rule = true;
+        if !action.eq_ignore_ascii_case("crawl-delay") {
+            return;
+        }
+
+        if let Ok(secs) = value.trim().parse::<f64>() {
+            if self.in_specific_group {
+                self.specific_delay = Some(secs);
+            } else if self.in_global_group {
+                self.global_delay = Some(secs);
+            }
+        }
+    }
+}
+
+// "SearchBot/1.0 (...)" -> "SearchBot", the name robots.txt groups and X-Robots-Tag address
+pub fn product_token(user_agent: &str) -> &str {
+    user_agent
+        .split(|c: char| !(c.is_ascii_alphabetic() || c == '-' || c == '_'))
+        .next()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eviction_keeps_recent_and_busy_origins() {
+        let start = Instant::now();
+        let mut hosts = (0..MAX_CACHED_ORIGINS)
+            .map(|i| {
+                let slot = HostSlot {
+                    entry: Arc::default(),
+                    last_used: start + Duration::from_secs(i as u64),
+                };
+                (format!("https://host{}.example", i), slot)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // The oldest origin is in the middle of a check
+        let busy = Arc::clone(&hosts["https://host0.example"].entry);
+
+        evict_least_recently_used(&mut hosts);
+
+        assert_eq!(hosts.len(), MAX_CACHED_ORIGINS - MAX_CACHED_ORIGINS / 4);
+        assert!(hosts.contains_key("https://host0.example"));
+        assert!(!hosts.contains_key("https://host1.example"));
+        assert!(hosts.contains_key(&format!("https://host{}.example", MAX_CACHED_ORIGINS - 1)));
+        drop(busy);
+    }
+
+    fn directives(robots_txt: &str) -> (Option<Duration>, Vec<String>) {
+        let mut directives = DirectivesHandler::new("SearchBot");
+        parse_robotstxt(robots_txt, &mut directives);
+        (directives.crawl_delay(), directives.sitemaps)
+    }
+
+    #[test]
+    fn our_group_wins_over_the_global_one() {
+        let robots_txt = "User-agent: *\nCrawl-delay: 10\nDisallow: /tmp\n\n\
+                          User-agent: searchbot\nCrawl-delay: 2.5\nDisallow:\n";
+        assert_eq!(directives(robots_txt).0, Some(Duration::from_millis(2500)));
+
+        // Order doesn't matter
+        let robots_txt = "User-agent: SearchBot\nCrawl-delay: 3\n\nUser-agent: *\nCrawl-delay: 10\n";
+        assert_eq!(directives(robots_txt).0, Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn global_group_applies_without_ours() {
+        let robots_txt = "User-agent: OtherBot\nCrawl-delay: 60\n\nUser-agent: *\nCrawl-delay: 4\n";
+        assert_eq!(directives(robots_txt).0, Some(Duration::from_secs(4)));
+
+        let robots_txt = "User-agent: OtherBot\nCrawl-delay: 60\n";
+        assert_eq!(directives(robots_txt).0, None);
+    }
+
+    #[test]
+    fn grouped_user_agents_share_rules() {
+        // Consecutive user-agent lines form one group, a rule line ends it
+        let robots_txt = "User-agent: OtherBot\nUser-agent: SearchBot\nCrawl-delay: 7\n\n\
+                          User-agent: ThirdBot\nCrawl-delay: 60\n";
+        assert_eq!(directives(robots_txt).0, Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn invalid_crawl_delays_are_ignored() {
+        for value in ["0", "-5", "soon", "NaN", "inf"] {
+            let robots_txt = format!("User-agent: *\nCrawl-delay: {}\n", value);
+            assert_eq!(directives(&robots_txt).0, None, "Crawl-delay: {}", value);
+        }
+    }
+
+    #[test]
+    fn sitemaps_are_collected_from_any_group() {
+        let robots_txt = "Sitemap: https://example.com/sitemap.xml\n\
+                          User-agent: OtherBot\nDisallow: /\nSitemap: https://cdn.example.net/news.xml.gz\n\
+                          Sitemap:\n";
+        assert_eq!(
+            directives(robots_txt).1,
+            ["https://example.com/sitemap.xml", "https://cdn.example.net/news.xml.gz"]
+        );
+    }
+
+    #[test]
+    fn product_token_drops_version_and_comment() {
+        assert_eq!(product_token("SearchBot/1.0 (+https://example.com)"), "SearchBot");
+        assert_eq!(product_token("my_crawler-bot"), "my_crawler-bot");
+    }
+
+    #[test]
+    fn huge_crawl_delay_is_capped() {
+        let robots_txt = "User-agent: *\nCrawl-delay: 99999999999999999999\nDisallow:\n";
+        assert_eq!(directives(robots_txt).0, Some(MAX_CRAWL_DELAY));
+    }
+
+    #[tokio::test]
+    async fn compressed_robots_txt_is_decoded() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+        use std::io::Write;
+        use wiremock::matchers::path;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let robots_txt = format!("User-agent: *\nDisallow: /private\nSitemap: {}/news.xml\n", server.uri());
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(robots_txt.as_bytes()).unwrap();
+        Mock::given(path("/robots.txt"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_raw(encoder.finish().unwrap(), "text/plain"),
+            )
+            .mount(&server)
+            .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let client = Client::builder().default_headers(headers).build().unwrap();
+        let robots = RobotsCache::new(client, "SearchBot/1.0");
+
+        let url = |path: &str| Url::parse(&format!("{}{}", server.uri(), path)).unwrap();
+        assert!(matches!(robots.check(&url("/private/page")).await, RobotsCheck::Disallowed));
+        assert!(matches!(robots.check(&url("/public")).await, RobotsCheck::Allowed { .. }));
+
+        let sitemaps = robots.take_sitemaps();
+        assert_eq!(sitemaps.len(), 1);
+        assert_eq!(sitemaps[0].0, format!("{}/news.xml", server.uri()));
+        assert_eq!(sitemaps[0].1, SitemapSource::Robots);
+    }
+
+    #[tokio::test]
+    async fn oversized_robots_txt_is_read_up_to_the_limit() {
+        use wiremock::matchers::path;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let filler = "# padding\n".repeat(MAX_ROBOTS_BYTES / 10);
+        let robots_txt = format!("User-agent: *\nDisallow: /private\n{}Disallow: /past-the-limit\n", filler);
+        Mock::given(path("/robots.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(robots_txt))
+            .mount(&server)
+            .await;
+
+        let robots = RobotsCache::new(Client::new(), "SearchBot/1.0");
+        let url = |path: &str| Url::parse(&format!("{}{}", server.uri(), path)).unwrap();
+        assert!(matches!(robots.check(&url("/private/page")).await, RobotsCheck::Disallowed));
+        assert!(matches!(robots.check(&url("/past-the-limit")).await, RobotsCheck::Allowed { .. }));
+    }
+}
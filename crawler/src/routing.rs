@@ -0,0 +1,128 @@
+use anyhow::Result;
+use url::Url;
+
+use crate::fetcher::{media_type, HTML_MEDIA_TYPES};
+
+enum RouteMatch {
+    // Matches the host itself and any subdomain
+    Domain(String),
+    // Primary language subtag, e.g. "bn" for "bn-BD"
+    Language(String),
+    // Media type without parameters, one of the HTML_MEDIA_TYPES the fetcher accepts
+    ContentType(String),
+}
+
+struct RouteRule {
+    matcher: RouteMatch,
+    index: String,
+}
+
+// Picks the index a document goes to; the first matching rule wins
+pub struct IndexRouter {
+    default_index: String,
+    rules: Vec<RouteRule>,
+}
+
+impl IndexRouter {
+    // Rules are comma separated `kind:value=index` entries, e.g.
+    // "domain:prothomalo.com=news,lang:bn=bengali,type:application/xhtml+xml=xhtml"
+    pub fn parse(default_index: String, spec: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+
+        for rule in spec.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (condition, index) = rule
+                .rsplit_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Index route '{}' is missing '=index'", rule))?;
+            let (kind, value) = condition
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("Index route '{}' is missing 'kind:'", rule))?;
+
+            let value = value.trim().to_lowercase();
+            let matcher = match kind.trim() {
+                "domain" => RouteMatch::Domain(value),
+                "lang" => RouteMatch::Language(value),
+                // Anything else is never fetched, so the rule could never match
+                "type" if !HTML_MEDIA_TYPES.contains(&value.as_str()) => anyhow::bail!(
+                    "Index route '{}' names a type the crawler doesn't fetch, use one of {}",
+                    rule,
+                    HTML_MEDIA_TYPES.join(", ")
+                ),
+                "type" => RouteMatch::ContentType(value),
+                other => anyhow::bail!("Unknown index route kind '{}' in '{}'", other, rule),
+            };
+
+            rules.push(RouteRule {
+                matcher,
+                index: index.trim().to_string(),
+            });
+        }
+
+        Ok(Self { default_index, rules })
+    }
+
+    pub fn route(&self, url: &Url, language: Option<&str>, content_type: &str) -> &str {
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        let language = language.map(primary_language);
+        let media_type = media_type(content_type);
+
+        self.rules
+            .iter()
+            .find(|rule| match &rule.matcher {
+                RouteMatch::Domain(domain) => {
+                    host == *domain || host.ends_with(&format!(".{}", domain))
+                }
+                RouteMatch::Language(code) => language.as_deref() == Some(code.as_str()),
+                RouteMatch::ContentType(media) => media_type == *media,
+            })
+            .map_or(&self.default_index, |rule| &rule.index)
+    }
+
+    // Every index a document may be routed to, default first
+    pub fn indexes(&self) -> Vec<&str> {
+        let mut indexes = vec![self.default_index.as_str()];
+        for rule in &self.rules {
+            if !indexes.contains(&rule.index.as_str()) {
+                indexes.push(&rule.index);
+            }
+        }
+        indexes
+    }
+}
+
+fn primary_language(tag: &str) -> String {
+    tag.split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let router = IndexRouter::parse(
+            "web_pages".to_string(),
+            "domain:example.com=news, lang:bn=bengali, type:application/xhtml+xml=xhtml",
+        )
+        .unwrap();
+        let route = |url: &str, language, content_type| {
+            router.route(&Url::parse(url).unwrap(), language, content_type).to_string()
+        };
+
+        assert_eq!(route("https://www.example.com/a", Some("bn"), "text/html"), "news");
+        assert_eq!(route("https://example.org/", Some("bn-BD"), "text/html"), "bengali");
+        assert_eq!(route("https://example.org/", None, "Application/XHTML+XML; charset=utf-8"), "xhtml");
+        assert_eq!(route("https://notexample.com/", Some("en"), "text/html"), "web_pages");
+        assert_eq!(router.indexes(), ["web_pages", "news", "bengali", "xhtml"]);
+    }
+
+    #[test]
+    fn types_that_are_never_fetched_are_rejected() {
+        assert!(IndexRouter::parse("web_pages".to_string(), "type:text/html=html").is_ok());
+        assert!(IndexRouter::parse("web_pages".to_string(), "type:application/pdf=pdf").is_err());
+        assert!(IndexRouter::parse("web_pages".to_string(), "size:10=big").is_err());
+    }
+}
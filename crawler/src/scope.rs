@@ -0,0 +1,150 @@
+use anyhow::Result;
+use regex::Regex;
+use url::Url;
+
+// Which discovered URLs may enter the frontier; seeds are always accepted
+#[derive(Debug, Clone, Default)]
+pub struct CrawlScope {
+    // Only follow links to the host of the page they were found on
+    pub same_host_only: bool,
+    // Hosts must end in one of these, e.g. "bd" or "prothomalo.com"; empty allows all
+    pub allowed_suffixes: Vec<String>,
+    // These domains and their subdomains are never crawled
+    pub denied_domains: Vec<String>,
+    // URLs must match at least one of these when any are given
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+    // Most rows in `urls` per host, 0 for no limit
+    pub max_urls_per_host: i64,
+}
+
+impl CrawlScope {
+    // Lists are comma separated, patterns whitespace separated
+    pub fn parse(
+        same_host_only: bool,
+        allowed_suffixes: &str,
+        denied_domains: &str,
+        include: &str,
+        exclude: &str,
+        max_urls_per_host: i64,
+    ) -> Result<Self> {
+        Ok(Self {
+            same_host_only,
+            allowed_suffixes: domain_list(allowed_suffixes),
+            denied_domains: domain_list(denied_domains),
+            include: patterns(include)?,
+            exclude: patterns(exclude)?,
+            max_urls_per_host,
+        })
+    }
+
+    // Checks everything but the per-host limit, which needs the database
+    pub fn allows(&self, url: &str, source: &Url) -> bool {
+        let Some(host) = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_lowercase)) else {
+            return false;
+        };
+
+        if self.same_host_only && source.host_str().map(str::to_lowercase).as_deref() != Some(host.as_str()) {
+            return false;
+        }
+        if !self.allowed_suffixes.is_empty()
+            && !self.allowed_suffixes.iter().any(|suffix| in_domain(&host, suffix))
+        {
+            return false;
+        }
+        if self.denied_domains.iter().any(|domain| in_domain(&host, domain)) {
+            return false;
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|pattern| pattern.is_match(url)) {
+            return false;
+        }
+        !self.exclude.iter().any(|pattern| pattern.is_match(url))
+    }
+}
+
+// The domain itself or any subdomain
+fn in_domain(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn domain_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+fn patterns(list: &str) -> Result<Vec<Regex>> {
+    list.split_whitespace()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid scope pattern '{}': {}", pattern, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> Url {
+        Url::parse("https://www.prothomalo.com/bangladesh").unwrap()
+    }
+
+    fn scope(allowed: &str, denied: &str, include: &str, exclude: &str) -> CrawlScope {
+        CrawlScope::parse(false, allowed, denied, include, exclude, 0).unwrap()
+    }
+
+    #[test]
+    fn same_host_only() {
+        let scope = CrawlScope {
+            same_host_only: true,
+            ..CrawlScope::default()
+        };
+        assert!(scope.allows("https://WWW.prothomalo.com/sports", &source()));
+        assert!(!scope.allows("https://prothomalo.com/sports", &source()));
+        assert!(!scope.allows("https://example.com/", &source()));
+        assert!(!scope.allows("not a url", &source()));
+    }
+
+    #[test]
+    fn allowed_suffixes_match_whole_labels() {
+        let scope = scope("bd, .prothomalo.com", "", "", "");
+        assert_eq!(scope.allowed_suffixes, ["bd", "prothomalo.com"]);
+
+        assert!(scope.allows("https://www.dhakatribune.com.bd/", &source()));
+        assert!(scope.allows("https://bd/", &source()));
+        assert!(scope.allows("https://en.prothomalo.com/", &source()));
+        assert!(!scope.allows("https://notbd.com/", &source()));
+        assert!(!scope.allows("https://example.bd.com/", &source()));
+        assert!(!scope.allows("https://notprothomalo.com/", &source()));
+    }
+
+    #[test]
+    fn denied_domains_cover_subdomains() {
+        let scope = scope("", "Facebook.com", "", "");
+        assert!(!scope.allows("https://facebook.com/page", &source()));
+        assert!(!scope.allows("https://m.facebook.com/page", &source()));
+        assert!(scope.allows("https://notfacebook.com/", &source()));
+        assert!(scope.allows("https://facebook.com.bd/", &source()));
+    }
+
+    #[test]
+    fn include_and_exclude_patterns() {
+        let scope = scope("", "", r"/news/ /sports/", r"\?print=1 \.pdf$");
+        assert!(scope.allows("https://example.com/news/1", &source()));
+        assert!(scope.allows("https://example.com/sports/cricket", &source()));
+        assert!(!scope.allows("https://example.com/about", &source()));
+        assert!(!scope.allows("https://example.com/news/1?print=1", &source()));
+        assert!(!scope.allows("https://example.com/news/report.pdf", &source()));
+
+        // Without include patterns everything not excluded is allowed
+        assert!(self::scope("", "", "", r"\.pdf$").allows("https://example.com/about", &source()));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let error = CrawlScope::parse(false, "", "", "/news/ (unclosed", "", 0).unwrap_err();
+        assert!(error.to_string().contains("'(unclosed'"), "{}", error);
+        assert!(CrawlScope::parse(false, "", "", "", "[z-a]", 0).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::CrawlError;
+use crate::parser::ParsedContent;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    pub url: String,
+    pub name: String,
+    pub title: String,
+    pub description: String,
+    pub icon: String,
+    pub content: String,
+    pub timestamp: String,
+}
+
+// Final result for the URLs whose documents were written together
+pub struct IndexOutcome {
+    pub url_ids: Vec<i32>,
+    pub result: Result<(), CrawlError>,
+}
+
+// Where documents end up. Writes may be buffered, so their results are reported
+// through poll_outcomes rather than by index()
+#[async_trait]
+pub trait SearchBackend: Send + Sync {
+    // Creates the index if needed and applies its schema/settings
+    async fn ensure_schema(&self, index: &str) -> Result<()>;
+
+    async fn index(&self, index: &str, url_id: i32, document: Document) -> Result<(), CrawlError>;
+
+    async fn delete(&self, index: &str, document_id: &str) -> Result<(), CrawlError>;
+
+    // Pushes out anything buffered
+    async fn flush(&self) -> Result<(), CrawlError>;
+
+    // Writes that finished since the last call
+    async fn poll_outcomes(&self) -> Result<Vec<IndexOutcome>, CrawlError>;
+
+    // Whether any write is still waiting for its outcome
+    fn has_pending(&self) -> bool;
+}
+
+impl Document {
+    pub fn new(url: &str, parsed: &ParsedContent) -> Self {
+        let ParsedContent { name, title, description, icon, content, .. } = parsed;
+
+        // Fallback for description if empty
+        let mut final_description = if description.is_empty() {
+            content
+                .chars()
+                .take(400) // Increase take for better sentence completion
+                .collect::<String>()
+                .trim()
+                .to_string()
+        } else {
+            description.to_string()
+        };
+
+        // Final polishing of the description: remove redundant whitespace and artifacts
+        final_description = final_description
+            .replace("  ", " ")
+            .replace(" .", ".")
+            .replace(" ,", ",")
+            .trim()
+            .to_string();
+
+        Self {
+            id: generate_id(url),
+            url: url.to_string(),
+            name: name.to_string(),
+            title: title.to_string(),
+            description: final_description,
+            icon: icon.to_string(),
+            content: content.to_string(),
+            timestamp: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+// Generate unique ID from URL
+pub fn generate_id(url: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(url.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
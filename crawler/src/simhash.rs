@@ -0,0 +1,161 @@
+use std::str::FromStr;
+
+// Pages with less text than this share too much boilerplate to compare reliably
+const MIN_WORDS: usize = 50;
+
+// Words per shingle
+const SHINGLE_SIZE: usize = 3;
+
+// Most differing bits for two pages to count as near-duplicates. With four 16-bit bands,
+// pages within 3 bits always share at least one band, which is what the lookup relies on
+pub const MAX_DISTANCE: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NearDuplicatePolicy {
+    Off,
+    // The first page seen keeps its document, later near-duplicates are not indexed
+    Skip,
+    // Near-duplicates are not written either, but recorded as represented by the first
+    // page's document, so the cluster shows up as that one document
+    Collapse,
+}
+
+impl FromStr for NearDuplicatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "skip" => Ok(Self::Skip),
+            "collapse" => Ok(Self::Collapse),
+            other => anyhow::bail!("Unknown near-duplicate policy '{}'", other),
+        }
+    }
+}
+
+// 64-bit SimHash over word shingles of the extracted content
+pub fn simhash(content: &str) -> Option<u64> {
+    let words = content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(SHINGLE_SIZE) {
+        let hash = fnv1a(&shingle.join(" "));
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |hash, (bit, _)| hash | (1 << bit)),
+    )
+}
+
+// The fingerprint split into four 16-bit bands, each stored in its own indexed column
+pub fn bands(hash: u64) -> [i32; 4] {
+    [0, 1, 2, 3].map(|band| ((hash >> (band * 16)) & 0xffff) as i32)
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Stable across builds and processes, unlike the std hasher
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = "The city council met on Tuesday evening to discuss the proposed budget for the \
+        coming year. Members debated funding for road repairs, public libraries and the new community \
+        center on the east side. Several residents spoke during the public comment period, asking the \
+        council to restore hours at the main library branch and to add more street lighting near the \
+        schools. The mayor said a final vote is expected next month after the finance committee reviews \
+        the revised numbers and publishes its report for the public.";
+
+    const RECIPE: &str = "Preheat the oven to two hundred degrees and butter a loaf tin. Whisk the eggs \
+        with the sugar until pale, then fold in the flour, baking powder and a pinch of salt. Stir in \
+        the mashed bananas, melted butter and chopped walnuts until just combined. Pour the batter into \
+        the tin, smooth the top and bake for about fifty minutes, until a skewer comes out clean. Leave \
+        the bread to cool in the tin for ten minutes before turning it out onto a rack to cool completely \
+        before slicing.";
+
+    // A long page of distinct words, as most indexed articles are
+    fn page_words() -> Vec<String> {
+        (0..800).map(|i| format!("w{}", i * 7919 % 5003)).collect()
+    }
+
+    #[test]
+    fn near_identical_texts_are_within_the_distance() {
+        let original = page_words();
+        let hash = simhash(&original.join(" ")).unwrap();
+
+        // One word changed, or a footer added, as on a mirror
+        let mut edited = original.clone();
+        edited[400] = "changed".to_string();
+        let footer = format!("{} share this page", original.join(" "));
+
+        for copy in [edited.join(" "), footer] {
+            let copy = simhash(&copy).unwrap();
+            assert_ne!(hash, copy);
+            assert!(distance(hash, copy) <= MAX_DISTANCE, "{} bits", distance(hash, copy));
+        }
+
+        // Case and punctuation are not part of the words
+        let article = simhash(ARTICLE).unwrap();
+        assert_eq!(simhash(&ARTICLE.to_uppercase().replace(',', ";")), Some(article));
+    }
+
+    #[test]
+    fn unrelated_texts_are_far_apart() {
+        let article = simhash(ARTICLE).unwrap();
+        let recipe = simhash(RECIPE).unwrap();
+        assert!(distance(article, recipe) > 3 * MAX_DISTANCE, "{} bits", distance(article, recipe));
+    }
+
+    #[test]
+    fn short_texts_have_no_fingerprint() {
+        let words = |count: usize| (0..count).map(|i| format!("word{}", i)).collect::<Vec<_>>().join(" ");
+        assert_eq!(simhash(&words(MIN_WORDS - 1)), None);
+        assert!(simhash(&words(MIN_WORDS)).is_some());
+        assert_eq!(simhash(" ,.;! "), None);
+    }
+
+    #[test]
+    fn hashes_within_the_distance_share_a_band() {
+        let hash = simhash(ARTICLE).unwrap();
+        assert_eq!(bands(0x0004_0003_0002_0001), [1, 2, 3, 4]);
+        assert_eq!(bands(u64::MAX), [0xffff; 4]);
+
+        // Every way of flipping MAX_DISTANCE bits leaves one band untouched
+        for a in 0..64 {
+            for b in a + 1..64 {
+                for c in b + 1..64 {
+                    let other = hash ^ (1 << a) ^ (1 << b) ^ (1 << c);
+                    assert_eq!(distance(hash, other), MAX_DISTANCE);
+                    let shared = bands(hash).iter().zip(bands(other)).filter(|(x, y)| **x == *y).count();
+                    assert!(shared >= 1, "bits {} {} {}", a, b, c);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::time::Duration;
+
+use crate::decompress;
+
+// Sitemaps may list at most this many URLs
+const MAX_ENTRIES: usize = 50_000;
+
+// Pages modified this recently get a priority boost
+const FRESH_LASTMOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// How a sitemap came to be known, stored in sitemaps.source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SitemapSource {
+    // A `Sitemap:` line in robots.txt
+    Robots,
+    // `/sitemap.xml` on a host whose robots.txt lists none
+    Guessed,
+    // Listed in a sitemap index
+    Index,
+}
+
+impl SitemapSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Robots => "robots",
+            Self::Guessed => "guessed",
+            Self::Index => "index",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "hourly" => Some(Self::Hourly),
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            "monthly" => Some(Self::Monthly),
+            "yearly" => Some(Self::Yearly),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+
+    // Recrawl interval the site suggests, before the recrawl policy's bounds apply
+    pub fn interval(&self) -> Duration {
+        const HOUR: u64 = 60 * 60;
+        match self {
+            Self::Always | Self::Hourly => Duration::from_secs(HOUR),
+            Self::Daily => Duration::from_secs(24 * HOUR),
+            Self::Weekly => Duration::from_secs(7 * 24 * HOUR),
+            Self::Monthly => Duration::from_secs(30 * 24 * HOUR),
+            Self::Yearly | Self::Never => Duration::from_secs(365 * 24 * HOUR),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SitemapEntry {
+    pub loc: String,
+    pub lastmod: Option<DateTime<Utc>>,
+    // 0.0 to 1.0, relative to other pages of the same site
+    pub priority: Option<f32>,
+    pub changefreq: Option<ChangeFreq>,
+}
+
+impl SitemapEntry {
+    // On the same scale as links (10 - depth): the sitemap priority maps to 5-10 with
+    // 0.5 when unset, and pages modified in the last week get one more
+    pub fn frontier_priority(&self) -> i32 {
+        let priority = self.priority.unwrap_or(0.5).clamp(0.0, 1.0);
+        let fresh = self
+            .lastmod
+            .and_then(|lastmod| (Utc::now() - lastmod).to_std().ok())
+            .is_some_and(|age| age <= FRESH_LASTMOD);
+
+        5 + (priority * 5.0).round() as i32 + i32::from(fresh)
+    }
+}
+
+pub enum Sitemap {
+    // <sitemapindex>: more sitemaps, with their lastmod
+    Index(Vec<(String, Option<DateTime<Utc>>)>),
+    // <urlset>: pages
+    UrlSet(Vec<SitemapEntry>),
+}
+
+// Parses a sitemap or sitemap index, gzip-compressed or not. A compressed sitemap may
+// expand to at most `max_decompressed` bytes, which stops compression bombs
+pub fn parse(body: &[u8], max_decompressed: usize) -> Result<Sitemap> {
+    // `.xml.gz` files are usually served as they are, without a Content-Encoding
+    let decompressed;
+    let body = if body.starts_with(&[0x1f, 0x8b]) {
+        decompressed = decompress::decode(body.to_vec(), "gzip", max_decompressed)?;
+        &decompressed
+    } else {
+        body
+    };
+
+    let text = String::from_utf8_lossy(body);
+    let document = roxmltree::Document::parse(&text)?;
+    let root = document.root_element();
+
+    // Elements are matched by local name, sitemaps in the wild get the namespace wrong
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+    let children = |name: &'static str| {
+        root.children()
+            .filter(move |node| node.tag_name().name() == name)
+            .take(MAX_ENTRIES)
+    };
+
+    match root.tag_name().name() {
+        "sitemapindex" => Ok(Sitemap::Index(
+            children("sitemap")
+                .filter_map(|node| {
+                    let loc = child_text(node, "loc")?;
+                    Some((loc, child_text(node, "lastmod").as_deref().and_then(parse_lastmod)))
+                })
+                .collect(),
+        )),
+        "urlset" => Ok(Sitemap::UrlSet(
+            children("url")
+                .filter_map(|node| {
+                    Some(SitemapEntry {
+                        loc: child_text(node, "loc")?,
+                        lastmod: child_text(node, "lastmod").as_deref().and_then(parse_lastmod),
+                        priority: child_text(node, "priority").and_then(|value| value.parse().ok()),
+                        changefreq: child_text(node, "changefreq").as_deref().and_then(ChangeFreq::parse),
+                    })
+                })
+                .collect(),
+        )),
+        other => anyhow::bail!("Not a sitemap: root element <{}>", other),
+    }
+}
+
+// W3C datetime: a full timestamp or just a date
+fn parse_lastmod(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc> http://example.com/a </loc>
+    <lastmod>2024-03-01</lastmod>
+    <changefreq>Daily</changefreq>
+    <priority>0.8</priority>
+  </url>
+  <url><loc>http://example.com/b</loc><lastmod>2024-03-01T10:30:00+02:00</lastmod></url>
+  <url><lastmod>2024-03-01</lastmod></url>
+  <url><loc>http://example.com/c</loc><lastmod>last tuesday</lastmod><changefreq>sometimes</changefreq></url>
+</urlset>"#;
+
+    fn urlset(body: &[u8]) -> Vec<SitemapEntry> {
+        match parse(body, 1024 * 1024).unwrap() {
+            Sitemap::UrlSet(entries) => entries,
+            Sitemap::Index(_) => panic!("parsed as an index"),
+        }
+    }
+
+    #[test]
+    fn urlset_entries() {
+        let entries = urlset(URLSET.as_bytes());
+        // The entry without a <loc> is dropped
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].loc, "http://example.com/a");
+        assert_eq!(entries[0].lastmod.unwrap().to_rfc3339(), "2024-03-01T00:00:00+00:00");
+        assert_eq!(entries[0].changefreq, Some(ChangeFreq::Daily));
+        assert_eq!(entries[0].priority, Some(0.8));
+
+        assert_eq!(entries[1].lastmod.unwrap().to_rfc3339(), "2024-03-01T08:30:00+00:00");
+        assert_eq!(entries[1].priority, None);
+
+        assert!(entries[2].lastmod.is_none());
+        assert!(entries[2].changefreq.is_none());
+    }
+
+    #[test]
+    fn sitemap_index() {
+        let body = br#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <sitemap><loc>http://example.com/posts.xml</loc><lastmod>2024-01-02</lastmod></sitemap>
+            <sitemap><loc>http://example.com/pages.xml.gz</loc></sitemap>
+        </sitemapindex>"#;
+
+        let Sitemap::Index(children) = parse(body, 1024).unwrap() else {
+            panic!("parsed as a urlset");
+        };
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].0, "http://example.com/posts.xml");
+        assert!(children[0].1.is_some());
+        assert_eq!(children[1], ("http://example.com/pages.xml.gz".to_string(), None));
+    }
+
+    #[test]
+    fn gzipped_sitemaps_are_decompressed_up_to_the_limit() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(URLSET.as_bytes()).unwrap();
+        let body = encoder.finish().unwrap();
+
+        assert_eq!(urlset(&body).len(), 3);
+        assert!(parse(&body, URLSET.len() - 1).is_err());
+    }
+
+    #[test]
+    fn other_documents_are_rejected() {
+        assert!(parse(b"<rss><channel/></rss>", 1024).is_err());
+        assert!(parse(b"<html><body>Not found</body></html>", 1024).is_err());
+        assert!(parse(b"not xml at all", 1024).is_err());
+    }
+
+    #[test]
+    fn frontier_priority_follows_priority_and_freshness() {
+        let entry = |priority, lastmod| SitemapEntry {
+            loc: "http://example.com/".to_string(),
+            lastmod,
+            priority,
+            changefreq: None,
+        };
+        let now = Some(Utc::now());
+        let old = Some(Utc::now() - chrono::Duration::days(30));
+
+        assert_eq!(entry(None, None).frontier_priority(), 8);
+        assert_eq!(entry(Some(1.0), old).frontier_priority(), 10);
+        assert_eq!(entry(Some(1.0), now).frontier_priority(), 11);
+        assert_eq!(entry(Some(0.0), None).frontier_priority(), 5);
+        assert_eq!(entry(Some(7.0), None).frontier_priority(), 10);
+    }
+}
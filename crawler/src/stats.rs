@@ -0,0 +1,82 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::fetcher::Transfer;
+
+// Counters accumulated in memory and added to today's crawl_stats row on flush
+#[derive(Default)]
+pub struct CrawlStats {
+    pages_crawled: AtomicU64,
+    pages_indexed: AtomicU64,
+    pages_unchanged: AtomicU64,
+    errors: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_decoded: AtomicU64,
+}
+
+impl CrawlStats {
+    // A page was fetched, whatever happened to it afterwards
+    pub fn record_crawled(&self) {
+        self.pages_crawled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Documents the search backend confirmed
+    pub fn record_indexed(&self, count: usize) {
+        self.pages_indexed.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    // A page whose index write was skipped because it had not changed
+    pub fn record_unchanged(&self) {
+        self.pages_unchanged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Body sizes of one response, compressed as sent and decoded
+    pub fn record_transfer(&self, transfer: Transfer) {
+        self.bytes_received.fetch_add(transfer.wire_bytes as u64, Ordering::Relaxed);
+        self.bytes_decoded.fetch_add(transfer.decoded_bytes as u64, Ordering::Relaxed);
+    }
+
+    // Adds everything counted since the last flush to today's row
+    pub async fn flush(&self, pool: &PgPool) -> Result<()> {
+        let counts = [
+            &self.pages_crawled,
+            &self.pages_indexed,
+            &self.pages_unchanged,
+            &self.errors,
+        ]
+        .map(|counter| counter.swap(0, Ordering::Relaxed) as i32);
+        let bytes = [&self.bytes_received, &self.bytes_decoded]
+            .map(|counter| counter.swap(0, Ordering::Relaxed) as i64);
+
+        if counts.iter().all(|count| *count == 0) && bytes.iter().all(|count| *count == 0) {
+            return Ok(());
+        }
+
+        sqlx::query(
+            "INSERT INTO crawl_stats (date, pages_crawled, pages_indexed, pages_unchanged, errors, bytes_received, bytes_decoded)
+             VALUES (CURRENT_DATE, $1, $2, $3, $4, $5, $6)
+             ON CONFLICT (date) DO UPDATE
+             SET pages_crawled = crawl_stats.pages_crawled + EXCLUDED.pages_crawled,
+                 pages_indexed = crawl_stats.pages_indexed + EXCLUDED.pages_indexed,
+                 pages_unchanged = crawl_stats.pages_unchanged + EXCLUDED.pages_unchanged,
+                 errors = crawl_stats.errors + EXCLUDED.errors,
+                 bytes_received = crawl_stats.bytes_received + EXCLUDED.bytes_received,
+                 bytes_decoded = crawl_stats.bytes_decoded + EXCLUDED.bytes_decoded"
+        )
+        .bind(counts[0])
+        .bind(counts[1])
+        .bind(counts[2])
+        .bind(counts[3])
+        .bind(bytes[0])
+        .bind(bytes[1])
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
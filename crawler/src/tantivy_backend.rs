@@ -0,0 +1,420 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tantivy::collector::TopDocs;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::QueryParser;
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value as _, FAST, INDEXED,
+    STORED, STRING,
+};
+use tantivy::tokenizer::{LowerCaser, RemoveLongFilter, SimpleTokenizer, StopWordFilter, TextAnalyzer};
+use tantivy::{Index, IndexWriter, TantivyDocument, TantivyError, Term};
+use tracing::{debug, info};
+
+use crate::error::CrawlError;
+use crate::search_backend::{Document, IndexOutcome, SearchBackend};
+
+const TOKENIZER: &str = "crawler";
+
+// Memory the writer may use before it flushes segments to disk
+const WRITER_MEMORY_BYTES: usize = 50 * 1024 * 1024;
+
+// Document text fields, in the order they are declared in the schema
+const TEXT_FIELDS: [&str; 6] = ["url", "name", "title", "description", "icon", "content"];
+
+struct Fields {
+    id: Field,
+    timestamp: Field,
+    text: Vec<(&'static str, Field)>,
+    // Fields queried by `search`, most important first
+    searchable: Vec<Field>,
+}
+
+struct TantivyIndex {
+    writer: Arc<Mutex<IndexWriter>>,
+    fields: Fields,
+    // URLs whose documents were added since the last commit
+    uncommitted: Mutex<Vec<i32>>,
+    dirty: AtomicBool,
+}
+
+// Writes documents to an on-disk Tantivy index per index name under `dir`,
+// with a schema derived from the same settings file as Meilisearch
+pub struct TantivyBackend {
+    dir: PathBuf,
+    settings: Value,
+    indexes: Mutex<HashMap<String, Arc<TantivyIndex>>>,
+    settled: Mutex<Vec<IndexOutcome>>,
+}
+
+impl TantivyBackend {
+    pub fn new(dir: PathBuf, settings: Value) -> Self {
+        Self {
+            dir,
+            settings,
+            indexes: Mutex::new(HashMap::new()),
+            settled: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn open(&self, index: &str) -> Result<Arc<TantivyIndex>, CrawlError> {
+        if let Some(opened) = self.indexes.lock().unwrap().get(index) {
+            return Ok(Arc::clone(opened));
+        }
+
+        let (opened, fields) = open_index(&self.dir.join(index), &self.settings)?;
+        let writer = opened.writer(WRITER_MEMORY_BYTES)?;
+        info!("Opened Tantivy index {} in {}", index, self.dir.display());
+
+        let opened = Arc::new(TantivyIndex {
+            writer: Arc::new(Mutex::new(writer)),
+            fields,
+            uncommitted: Mutex::new(Vec::new()),
+            dirty: AtomicBool::new(false),
+        });
+        Ok(Arc::clone(
+            self.indexes
+                .lock()
+                .unwrap()
+                .entry(index.to_string())
+                .or_insert(opened),
+        ))
+    }
+}
+
+#[async_trait]
+impl SearchBackend for TantivyBackend {
+    async fn ensure_schema(&self, index: &str) -> Result<()> {
+        self.open(index)?;
+        Ok(())
+    }
+
+    async fn index(&self, index: &str, url_id: i32, document: Document) -> Result<(), CrawlError> {
+        let opened = self.open(index)?;
+        let fields = &opened.fields;
+
+        let mut doc = TantivyDocument::default();
+        doc.add_text(fields.id, &document.id);
+        for (name, field) in &fields.text {
+            let value = match *name {
+                "url" => &document.url,
+                "name" => &document.name,
+                "title" => &document.title,
+                "description" => &document.description,
+                "icon" => &document.icon,
+                _ => &document.content,
+            };
+            doc.add_text(*field, value);
+        }
+        if let Ok(timestamp) = chrono::DateTime::parse_from_rfc3339(&document.timestamp) {
+            doc.add_date(fields.timestamp, tantivy::DateTime::from_timestamp_secs(timestamp.timestamp()));
+        }
+
+        // Replace any earlier version of the page
+        {
+            let writer = opened.writer.lock().unwrap();
+            writer.delete_term(Term::from_field_text(fields.id, &document.id));
+            writer.add_document(doc)?;
+        }
+        opened.uncommitted.lock().unwrap().push(url_id);
+        opened.dirty.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    async fn delete(&self, index: &str, document_id: &str) -> Result<(), CrawlError> {
+        let opened = self.open(index)?;
+        opened
+            .writer
+            .lock()
+            .unwrap()
+            .delete_term(Term::from_field_text(opened.fields.id, document_id));
+        opened.dirty.store(true, Ordering::SeqCst);
+
+        debug!("Deleted document {} from {}", document_id, index);
+
+        Ok(())
+    }
+
+    // Commits every index with changes; documents only become searchable here
+    async fn flush(&self) -> Result<(), CrawlError> {
+        let indexes = self.indexes.lock().unwrap().values().cloned().collect::<Vec<_>>();
+
+        for opened in indexes {
+            if !opened.dirty.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+
+            // Taken before committing, so every id here belongs to a document in the commit
+            let url_ids = mem::take(&mut *opened.uncommitted.lock().unwrap());
+            let writer = Arc::clone(&opened.writer);
+            let result = tokio::task::spawn_blocking(move || writer.lock().unwrap().commit().map(|_| ()))
+                .await
+                .unwrap_or_else(|e| Err(TantivyError::SystemError(e.to_string())))
+                .map_err(CrawlError::from);
+
+            if !url_ids.is_empty() {
+                self.settled.lock().unwrap().push(IndexOutcome { url_ids, result });
+            } else {
+                result?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn poll_outcomes(&self) -> Result<Vec<IndexOutcome>, CrawlError> {
+        Ok(mem::take(&mut *self.settled.lock().unwrap()))
+    }
+
+    fn has_pending(&self) -> bool {
+        self.indexes
+            .lock()
+            .unwrap()
+            .values()
+            .any(|opened| !opened.uncommitted.lock().unwrap().is_empty())
+    }
+}
+
+// Runs a query against the committed documents of one index, best matches first
+pub fn search(dir: &Path, index: &str, settings: &Value, query: &str, limit: usize) -> Result<Vec<Document>> {
+    let (opened, fields) = open_index(&dir.join(index), settings)?;
+    let searcher = opened.reader()?.searcher();
+
+    let mut parser = QueryParser::for_index(&opened, fields.searchable.clone());
+    // Like Meilisearch's attribute rule, earlier searchable attributes weigh more
+    for (rank, field) in fields.searchable.iter().enumerate() {
+        parser.set_field_boost(*field, (fields.searchable.len() - rank) as f32);
+    }
+    let query = parser.parse_query(query)?;
+
+    let mut documents = Vec::new();
+    for (_score, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+        let stored: TantivyDocument = searcher.doc(address)?;
+        let text = |field: Field| {
+            stored
+                .get_first(field)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let named = |name: &str| {
+            fields
+                .text
+                .iter()
+                .find(|(field_name, _)| *field_name == name)
+                .map(|(_, field)| text(*field))
+                .unwrap_or_default()
+        };
+
+        documents.push(Document {
+            id: text(fields.id),
+            url: named("url"),
+            name: named("name"),
+            title: named("title"),
+            description: named("description"),
+            icon: named("icon"),
+            content: named("content"),
+            timestamp: stored
+                .get_first(fields.timestamp)
+                .and_then(|value| value.as_datetime())
+                .and_then(|date| chrono::DateTime::from_timestamp(date.into_timestamp_secs(), 0))
+                .map(|date| date.to_rfc3339())
+                .unwrap_or_default(),
+        });
+    }
+
+    Ok(documents)
+}
+
+fn open_index(path: &Path, settings: &Value) -> Result<(Index, Fields), TantivyError> {
+    let (schema, fields) = build_schema(settings);
+
+    std::fs::create_dir_all(path)?;
+    // Fails if the directory holds an index built from different settings
+    let index = Index::open_or_create(MmapDirectory::open(path)?, schema)?;
+
+    let stop_words = string_list(settings, "stopWords").unwrap_or_default();
+    index.tokenizers().register(
+        TOKENIZER,
+        TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(StopWordFilter::remove(stop_words))
+            .build(),
+    );
+
+    Ok((index, fields))
+}
+
+// searchableAttributes become indexed text fields and displayedAttributes stored ones;
+// the id is always an exact-match key and the timestamp a sortable fast field
+fn build_schema(settings: &Value) -> (Schema, Fields) {
+    let searchable = string_list(settings, "searchableAttributes");
+    let displayed = string_list(settings, "displayedAttributes");
+    let listed = |list: &Option<Vec<String>>, name: &str| {
+        list.as_ref()
+            .is_none_or(|list| list.iter().any(|attribute| attribute == name || attribute == "*"))
+    };
+
+    let mut builder = Schema::builder();
+    let id = builder.add_text_field("id", STRING | STORED);
+    let timestamp = builder.add_date_field("timestamp", INDEXED | STORED | FAST);
+
+    let mut text = Vec::new();
+    for name in TEXT_FIELDS {
+        let mut options = TextOptions::default();
+        if listed(&searchable, name) {
+            options = options.set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(TOKENIZER)
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            );
+        }
+        if listed(&displayed, name) {
+            options = options.set_stored();
+        }
+        text.push((name, builder.add_text_field(name, options)));
+    }
+
+    // Query order follows searchableAttributes rather than the schema
+    let mut searchable_fields = Vec::new();
+    match &searchable {
+        Some(list) if !list.iter().any(|attribute| attribute == "*") => {
+            for attribute in list {
+                if let Some((_, field)) = text.iter().find(|(name, _)| name == attribute) {
+                    searchable_fields.push(*field);
+                }
+            }
+        }
+        _ => searchable_fields.extend(text.iter().map(|(_, field)| *field)),
+    }
+
+    let fields = Fields {
+        id,
+        timestamp,
+        text,
+        searchable: searchable_fields,
+    };
+    (builder.build(), fields)
+}
+
+fn string_list(settings: &Value, key: &str) -> Option<Vec<String>> {
+    settings.get(key).and_then(Value::as_array).map(|values| {
+        values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn settings() -> Value {
+        json!({
+            "searchableAttributes": ["title", "content"],
+            "displayedAttributes": ["url", "title", "content"],
+            "stopWords": ["the"]
+        })
+    }
+
+    fn document(id: &str, title: &str, content: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            url: format!("https://example.com/{}", id),
+            title: title.to_string(),
+            content: content.to_string(),
+            timestamp: "2024-03-05T10:00:00+00:00".to_string(),
+            ..Document::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn flushed_documents_are_searchable_and_settled() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = TantivyBackend::new(dir.path().to_path_buf(), settings());
+
+        backend.index("pages", 1, document("a", "Rust crawler", "Fetches pages politely")).await.unwrap();
+        backend.index("pages", 2, document("b", "Gardening", "Tomatoes need sun")).await.unwrap();
+        assert!(backend.has_pending());
+        // Nothing is visible before the commit
+        assert!(search(dir.path(), "pages", &settings(), "crawler", 10).unwrap().is_empty());
+
+        backend.flush().await.unwrap();
+        assert!(!backend.has_pending());
+        let outcomes = backend.poll_outcomes().await.unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].url_ids, [1, 2]);
+        assert!(outcomes[0].result.is_ok());
+
+        let found = search(dir.path(), "pages", &settings(), "politely", 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "a");
+        assert_eq!(found[0].url, "https://example.com/a");
+        assert_eq!(found[0].timestamp, "2024-03-05T10:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn reindexing_replaces_and_deleting_removes() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = TantivyBackend::new(dir.path().to_path_buf(), settings());
+        let titles = |query: &str| {
+            search(dir.path(), "pages", &settings(), query, 10)
+                .unwrap()
+                .into_iter()
+                .map(|document| document.title)
+                .collect::<Vec<_>>()
+        };
+
+        backend.index("pages", 1, document("a", "First draft", "shared words")).await.unwrap();
+        backend.flush().await.unwrap();
+        backend.index("pages", 1, document("a", "Second draft", "shared words")).await.unwrap();
+        backend.flush().await.unwrap();
+        assert_eq!(titles("shared"), ["Second draft"]);
+        assert!(titles("first").is_empty());
+
+        backend.delete("pages", "a").await.unwrap();
+        backend.flush().await.unwrap();
+        assert!(titles("shared").is_empty());
+        // A commit without indexed documents settles no URLs
+        assert_eq!(backend.poll_outcomes().await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn schema_follows_searchable_and_displayed_attributes() {
+        let (schema, fields) = build_schema(&settings());
+        let entry = |name: &str| schema.get_field_entry(schema.get_field(name).unwrap()).clone();
+
+        for (name, indexed, stored) in [
+            ("title", true, true),
+            ("content", true, true),
+            ("url", false, true),
+            ("icon", false, false),
+            ("description", false, false),
+        ] {
+            assert_eq!(entry(name).is_indexed(), indexed, "{} indexed", name);
+            assert_eq!(entry(name).is_stored(), stored, "{} stored", name);
+        }
+        assert!(entry("id").is_indexed() && entry("id").is_stored());
+        assert!(entry("timestamp").is_fast());
+
+        let searchable = fields.searchable.iter().map(|field| schema.get_field_name(*field)).collect::<Vec<_>>();
+        assert_eq!(searchable, ["title", "content"]);
+
+        // Without the lists every text field is searchable and stored
+        let (schema, fields) = build_schema(&json!({}));
+        assert_eq!(fields.searchable.len(), TEXT_FIELDS.len());
+        let icon = schema.get_field_entry(schema.get_field("icon").unwrap());
+        assert!(icon.is_indexed() && icon.is_stored());
+    }
+}
@@ -1,17 +1,42 @@
 -- Reset Database (Use with caution!)
 DROP VIEW IF EXISTS crawl_progress;
+DROP VIEW IF EXISTS crawl_errors;
 DROP TABLE IF EXISTS crawl_stats;
+DROP TABLE IF EXISTS sitemaps;
+DROP TABLE IF EXISTS feeds;
 DROP TABLE IF EXISTS urls;
 
 -- URL Queue Table
+-- status: pending | processing | indexing | completed | failed | robots_blocked | index_failed | removed | duplicate | too_large
 CREATE TABLE IF NOT EXISTS urls (
     id SERIAL PRIMARY KEY,
     url TEXT UNIQUE NOT NULL,
+    host VARCHAR(255),
     status VARCHAR(20) DEFAULT 'pending',
     depth INTEGER DEFAULT 0,
     priority INTEGER DEFAULT 0,
     last_crawled TIMESTAMP,
     error_count INTEGER DEFAULT 0,
+    next_attempt_at TIMESTAMP,
+    last_error_kind VARCHAR(32),
+    last_error TEXT,
+    leased_until TIMESTAMP,
+    worker_id TEXT,
+    index_name VARCHAR(255),
+    -- Usually the SHA-256 of url; differs when indexed under rel=canonical or collapsed
+    document_id VARCHAR(64),
+    content_hash VARCHAR(64),
+    recrawl_interval_secs INTEGER,
+    next_crawl_at TIMESTAMP,
+    etag TEXT,
+    last_modified TEXT,
+    -- SimHash of the extracted text, also split into 16-bit bands for neighbor lookups
+    simhash BIGINT,
+    simhash_band0 INTEGER,
+    simhash_band1 INTEGER,
+    simhash_band2 INTEGER,
+    simhash_band3 INTEGER,
+    duplicate_of INTEGER REFERENCES urls(id) ON DELETE SET NULL,
     created_at TIMESTAMP DEFAULT NOW(),
     updated_at TIMESTAMP DEFAULT NOW()
 );
@@ -20,6 +45,49 @@ CREATE TABLE IF NOT EXISTS urls (
 CREATE INDEX IF NOT EXISTS idx_status_priority ON urls(status, priority DESC);
 CREATE INDEX IF NOT EXISTS idx_last_crawled ON urls(last_crawled);
 CREATE INDEX IF NOT EXISTS idx_depth ON urls(depth);
+CREATE INDEX IF NOT EXISTS idx_host ON urls(host);
+CREATE INDEX IF NOT EXISTS idx_pending_host ON urls(host, priority DESC, created_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_next_attempt_at ON urls(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_next_crawl_at ON urls(next_crawl_at) WHERE status IN ('completed', 'removed', 'duplicate');
+CREATE INDEX IF NOT EXISTS idx_simhash_band0 ON urls(simhash_band0) WHERE duplicate_of IS NULL;
+CREATE INDEX IF NOT EXISTS idx_simhash_band1 ON urls(simhash_band1) WHERE duplicate_of IS NULL;
+CREATE INDEX IF NOT EXISTS idx_simhash_band2 ON urls(simhash_band2) WHERE duplicate_of IS NULL;
+CREATE INDEX IF NOT EXISTS idx_simhash_band3 ON urls(simhash_band3) WHERE duplicate_of IS NULL;
+CREATE INDEX IF NOT EXISTS idx_leased_until ON urls(leased_until) WHERE status IN ('processing', 'indexing');
+
+-- Sitemaps found in robots.txt, guessed at /sitemap.xml or listed in a sitemap index
+-- source: robots | guessed | index
+CREATE TABLE IF NOT EXISTS sitemaps (
+    id SERIAL PRIMARY KEY,
+    url TEXT UNIQUE NOT NULL,
+    source VARCHAR(20) NOT NULL,
+    -- Origin whose robots.txt declared the sitemap; entries must be on its host
+    site TEXT NOT NULL,
+    next_fetch_at TIMESTAMP DEFAULT NOW(),
+    last_fetched TIMESTAMP,
+    url_count INTEGER DEFAULT 0,
+    error_count INTEGER DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_sitemaps_next_fetch_at ON sitemaps(next_fetch_at);
+
+-- RSS and Atom feeds advertised by crawled pages, polled faster while they publish
+CREATE TABLE IF NOT EXISTS feeds (
+    id SERIAL PRIMARY KEY,
+    url TEXT UNIQUE NOT NULL,
+    found_on TEXT,
+    poll_interval_secs INTEGER NOT NULL,
+    next_poll_at TIMESTAMP DEFAULT NOW(),
+    last_polled TIMESTAMP,
+    last_new_items INTEGER DEFAULT 0,
+    error_count INTEGER DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_feeds_next_poll_at ON feeds(next_poll_at);
 
 -- Crawl Statistics Table
 CREATE TABLE IF NOT EXISTS crawl_stats (
@@ -27,7 +95,11 @@ CREATE TABLE IF NOT EXISTS crawl_stats (
     date DATE UNIQUE NOT NULL DEFAULT CURRENT_DATE,
     pages_crawled INTEGER DEFAULT 0,
     pages_indexed INTEGER DEFAULT 0,
+    pages_unchanged INTEGER DEFAULT 0,
     errors INTEGER DEFAULT 0,
+    -- Response bodies as sent (possibly compressed) and after decoding
+    bytes_received BIGINT DEFAULT 0,
+    bytes_decoded BIGINT DEFAULT 0,
     avg_response_time_ms INTEGER DEFAULT 0,
     created_at TIMESTAMP DEFAULT NOW()
 );
@@ -46,12 +118,12 @@ CREATE TRIGGER update_urls_updated_at BEFORE UPDATE ON urls
     FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
 
 -- Insert some seed URLs to start crawling
-INSERT INTO urls (url, priority, depth) VALUES
-    ('https://en.wikipedia.org/wiki/Artificial_intelligence', 10, 0),
-    ('https://news.ycombinator.com/', 9, 0),
-    ('https://www.reddit.com/r/programming/', 8, 0),
-    ('https://github.com/trending', 8, 0),
-    ('https://stackoverflow.com/', 7, 0)
+INSERT INTO urls (url, host, priority, depth) VALUES
+    ('https://en.wikipedia.org/wiki/Artificial_intelligence', 'en.wikipedia.org', 10, 0),
+    ('https://news.ycombinator.com/', 'news.ycombinator.com', 9, 0),
+    ('https://www.reddit.com/r/programming/', 'www.reddit.com', 8, 0),
+    ('https://github.com/trending', 'github.com', 8, 0),
+    ('https://stackoverflow.com/', 'stackoverflow.com', 7, 0)
 ON CONFLICT (url) DO NOTHING;
 
 -- View for monitoring crawl progress
@@ -63,3 +135,14 @@ SELECT
     MAX(last_crawled) as last_activity
 FROM urls
 GROUP BY status;
+
+-- Why pages are failing or being retried, e.g. timeout, dns, http_4xx, http_5xx, not_html, index
+CREATE OR REPLACE VIEW crawl_errors AS
+SELECT 
+    last_error_kind,
+    status,
+    COUNT(*) as count,
+    MAX(last_crawled) as last_seen
+FROM urls
+WHERE last_error_kind IS NOT NULL
+GROUP BY last_error_kind, status;